@@ -0,0 +1,173 @@
+use crate::net::TtyAxumState;
+use crate::queue::{QueueEditError, QueueInfo, QueuePushError, RequestStatus};
+use crate::video::VideoRequest;
+use axum::extract::rejection::JsonRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use console::style;
+
+// Versioned json api, for clients that aren't request instances (e.g. browser extensions).
+// Unlike the other routes, errors are json too: {"error": {"code": "...", "message": "..."}}
+pub(crate) const PREFIX: &str = "/api/v1";
+
+pub(crate) fn router() -> axum::Router<TtyAxumState> {
+    axum::Router::new()
+        .route("/requests", get(list_requests).post(create_request))
+        .route("/requests/{id}", get(get_request).delete(cancel_request))
+}
+
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status_code: StatusCode,
+    // stable, machine readable reason
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub(crate) fn new(status_code: StatusCode, code: &'static str, message: String) -> Self {
+        Self {
+            status_code,
+            code,
+            message,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+            }
+        });
+
+        (self.status_code, axum::Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_body", rejection.body_text())
+    }
+}
+
+impl From<QueueEditError> for ApiError {
+    fn from(err: QueueEditError) -> Self {
+        match err {
+            QueueEditError::NotFound(_) => ApiError::new(StatusCode::NOT_FOUND, "not_found", err.to_string()),
+            QueueEditError::Current(_) => ApiError::new(StatusCode::CONFLICT, "in_progress", err.to_string()),
+        }
+    }
+}
+
+// the tty parses the url, so that clients don't have to know about YouTube's url shapes
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NewRequest {
+    url: String,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    playlist: bool,
+    #[serde(default)]
+    priority: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct CreatedRequest {
+    id: u64,
+    source_url: String,
+    // unix timestamp, set when the tty already imported the same video
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imported_at: Option<u64>,
+}
+
+async fn create_request(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+    body: Result<axum::Json<NewRequest>, JsonRejection>,
+) -> Result<(StatusCode, axum::Json<CreatedRequest>), ApiError> {
+    let axum::Json(new_request) = body?;
+
+    // api clients don't have a pid
+    let vreq = VideoRequest::from_url(&new_request.url, 0, new_request.profile, new_request.playlist)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, "invalid_url", err.to_string()))?;
+
+    let Some(args) = state.profiles.get(vreq.profile.as_deref()) else {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "unknown_profile",
+            format!("Unknown profile '{}'", vreq.profile.unwrap_or_default()),
+        ));
+    };
+    vreq.validate(&args.allowed_hosts)
+        .map_err(|reason| ApiError::new(StatusCode::FORBIDDEN, "rejected", reason))?;
+
+    let source_url = vreq.source_url();
+    let imported_at = state.queue.imported_at(&vreq);
+    let result = state
+        .queue
+        .try_push_batch(vec![vreq], new_request.priority)
+        .pop()
+        .expect("try_push_batch returns one result per request");
+
+    match result {
+        Ok(id) => {
+            println!("{} {source_url} (id: {id}) through the api", style("Enqueued").green());
+            Ok((
+                StatusCode::CREATED,
+                axum::Json(CreatedRequest {
+                    id,
+                    source_url,
+                    imported_at,
+                }),
+            ))
+        }
+        Err(err @ QueuePushError::Full(_)) => Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "queue_full",
+            err.to_string(),
+        )),
+        Err(err @ QueuePushError::Duplicate(_)) => {
+            Err(ApiError::new(StatusCode::CONFLICT, "duplicate", err.to_string()))
+        }
+        Err(err @ QueuePushError::Stopping(_)) => Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "stopping",
+            err.to_string(),
+        )),
+    }
+}
+
+async fn list_requests(axum::extract::State(state): axum::extract::State<TtyAxumState>) -> axum::Json<QueueInfo> {
+    axum::Json(state.queue.info(&state.profiles.selected().profile))
+}
+
+async fn get_request(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+) -> Result<axum::Json<RequestStatus>, ApiError> {
+    state.queue.status(id).map(axum::Json).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!("Unknown video request id {id}"),
+        )
+    })
+}
+
+async fn cancel_request(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    let vreq = state.queue.remove(id)?;
+    println!(
+        "{} video request {id} ({}) from the queue",
+        style("Cancelled").yellow(),
+        vreq.source_url()
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
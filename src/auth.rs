@@ -0,0 +1,264 @@
+use crate::api;
+use crate::lock::TtyEndpoint;
+use crate::net::HttpMsgResponse;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use std::io::Read;
+use std::sync::Arc;
+
+// Every request to the tty must carry the token as 'Authorization: Bearer <token>'.
+// Browsers can't add that header to a cross-site form post, so web pages can't enqueue anything.
+pub(crate) fn generate_token() -> Result<String, std::io::Error> {
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthState {
+    token: Arc<str>,
+    // what the Host header can be, anything else comes from a (rebinded) foreign domain
+    allowed_hosts: Arc<[String]>,
+    // web pages (or browser extensions) allowed to talk to the tty, see '--cors-origins'
+    cors_origins: Arc<[String]>,
+}
+
+impl AuthState {
+    pub(crate) fn new(token: String, endpoint: &TtyEndpoint, cors_origins: &[String]) -> Self {
+        let allowed_hosts = match endpoint {
+            TtyEndpoint::Port(port) => vec![
+                format!("127.0.0.1:{port}"),
+                format!("localhost:{port}"),
+                format!("[::1]:{port}"),
+            ],
+            TtyEndpoint::UnixSocket(_) => vec![String::from("localhost")],
+        };
+
+        Self {
+            token: token.into(),
+            allowed_hosts: allowed_hosts.into(),
+            cors_origins: cors_origins.into(),
+        }
+    }
+
+    // the status code, api error code and message to reject the request with, if it must be
+    fn check(
+        &self,
+        path: &str,
+        host: Option<&str>,
+        origin: Option<&str>,
+        authorization: Option<&str>,
+    ) -> Result<(), (StatusCode, &'static str, &'static str)> {
+        if !host.is_some_and(|host| self.allowed_hosts.iter().any(|allowed| allowed == host)) {
+            return Err((StatusCode::FORBIDDEN, "forbidden", "Unexpected Host header"));
+        }
+
+        // only set by browsers, which must be on one of our own pages (or an allowed origin, for the json api only)
+        if let Some(origin) = origin {
+            let allowed_origin = (path.starts_with(api::PREFIX)
+                && self.cors_origins.iter().any(|allowed| allowed == origin))
+                || self
+                    .allowed_hosts
+                    .iter()
+                    .any(|allowed| origin.strip_prefix("http://") == Some(allowed.as_str()));
+            if !allowed_origin {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "forbidden",
+                    "Cross-origin requests aren't allowed",
+                ));
+            }
+        }
+
+        let token = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        if !token.is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes())) {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "Missing or wrong tty token (see the lockfile)",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) async fn check_request(
+    axum::extract::State(auth): axum::extract::State<AuthState>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let headers = request.headers();
+    // nested routers only see the end of the path
+    let path = match request.extensions().get::<axum::extract::OriginalUri>() {
+        Some(original_uri) => original_uri.path(),
+        None => request.uri().path(),
+    };
+    let reject = |status_code: StatusCode, code: &'static str, msg: &str| {
+        if path.starts_with(api::PREFIX) {
+            api::ApiError::new(status_code, code, String::from(msg)).into_response()
+        } else {
+            HttpMsgResponse::new(status_code, format!("{msg}!")).into_response()
+        }
+    };
+
+    // http/2 requests don't have a Host header
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().authority().map(|authority| authority.as_str()));
+    // an origin that isn't valid utf-8 is never allowed
+    let origin = headers
+        .get(header::ORIGIN)
+        .map(|origin| origin.to_str().unwrap_or_default());
+    let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+
+    if let Err((status_code, code, msg)) = auth.check(path, host, origin, authorization) {
+        return reject(status_code, code, msg);
+    }
+
+    next.run(request).await
+}
+
+// doesn't stop at the first different byte, so the response time doesn't tell how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef";
+    const BEARER: Option<&str> = Some("Bearer 0123456789abcdef");
+
+    fn port_auth() -> AuthState {
+        AuthState::new(
+            String::from(TOKEN),
+            &TtyEndpoint::Port(8080),
+            &[String::from("moz-extension://abcd")],
+        )
+    }
+
+    fn status(result: Result<(), (StatusCode, &'static str, &'static str)>) -> Option<StatusCode> {
+        result.err().map(|(status_code, _, _)| status_code)
+    }
+
+    #[test]
+    fn generates_random_tokens() {
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, generate_token().unwrap());
+    }
+
+    #[test]
+    fn checks_the_host() {
+        let auth = port_auth();
+        for host in ["127.0.0.1:8080", "localhost:8080", "[::1]:8080"] {
+            assert_eq!(status(auth.check("/queue", Some(host), None, BEARER)), None, "{host}");
+        }
+        // dns rebinding, or another port
+        for host in [
+            None,
+            Some("evil.example:8080"),
+            Some("127.0.0.1:8081"),
+            Some("localhost"),
+        ] {
+            assert_eq!(
+                status(auth.check("/queue", host, None, BEARER)),
+                Some(StatusCode::FORBIDDEN),
+                "{host:?}"
+            );
+        }
+
+        let socket_auth = AuthState::new(String::from(TOKEN), &TtyEndpoint::UnixSocket("/tmp/s".into()), &[]);
+        assert_eq!(
+            status(socket_auth.check("/queue", Some("localhost"), None, BEARER)),
+            None
+        );
+        assert_eq!(
+            status(socket_auth.check("/queue", Some("localhost:8080"), None, BEARER)),
+            Some(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn checks_the_origin() {
+        let auth = port_auth();
+        let host = Some("127.0.0.1:8080");
+
+        // our own pages
+        assert_eq!(
+            status(auth.check("/queue", host, Some("http://127.0.0.1:8080"), BEARER)),
+            None
+        );
+        assert_eq!(
+            status(auth.check("/queue", host, Some("http://localhost:8080"), BEARER)),
+            None
+        );
+
+        // '--cors-origins' only opens the json api
+        assert_eq!(
+            status(auth.check("/api/v1/queue", host, Some("moz-extension://abcd"), BEARER)),
+            None
+        );
+        for path in ["/stop", "/queue/1", "/review"] {
+            assert_eq!(
+                status(auth.check(path, host, Some("moz-extension://abcd"), BEARER)),
+                Some(StatusCode::FORBIDDEN),
+                "{path}"
+            );
+        }
+
+        for origin in [
+            "https://evil.example",
+            "https://127.0.0.1:8080",
+            "moz-extension://other",
+            "",
+            "null",
+        ] {
+            assert_eq!(
+                status(auth.check("/api/v1/queue", host, Some(origin), BEARER)),
+                Some(StatusCode::FORBIDDEN),
+                "{origin}"
+            );
+        }
+    }
+
+    #[test]
+    fn checks_the_token() {
+        let auth = port_auth();
+        let host = Some("127.0.0.1:8080");
+
+        for authorization in [
+            None,
+            Some(TOKEN),
+            Some("Bearer "),
+            Some("Bearer 0123456789abcdeF"),
+            Some("Bearer 0123456789abcdef0"),
+            Some("Basic 0123456789abcdef"),
+        ] {
+            assert_eq!(
+                status(auth.check("/queue", host, None, authorization)),
+                Some(StatusCode::UNAUTHORIZED),
+                "{authorization:?}"
+            );
+        }
+
+        // the Host and Origin checks come first, they don't tell anything about the token
+        assert_eq!(
+            status(auth.check("/queue", Some("evil.example"), None, None)),
+            Some(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
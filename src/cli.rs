@@ -1,5 +1,7 @@
+use crate::utils::time;
+use crate::video::Outcome;
 use anyhow::anyhow;
-pub(crate) use clap::Parser;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
@@ -18,14 +20,40 @@ pub(crate) enum Command {
     Tty(TtyArgs),
     #[command(about = "Send a video request to the tty instance")]
     Request(RequestArgs),
+    #[command(about = "Show the video requests waiting in the tty instance's queue")]
+    Queue(QueueArgs),
+    #[command(about = "List the running tty instances")]
+    Instances(InstancesArgs),
+    #[command(
+        about = "Show whether the tty instance is running and what it's doing. Exits with 0 if it's running, 3 if it isn't"
+    )]
+    Status(StatusArgs),
+    #[command(
+        about = "Stop the tty instance, once the current video request is done (or right away with '--now'). Exits with 3 if it isn't running"
+    )]
+    Stop(StopArgs),
+    #[command(
+        about = "Start a review session in the tty instance's terminal, for the video requests it parked (see '--headless' and '--defer-review')"
+    )]
+    Review(ReviewArgs),
+    #[command(about = "List the video requests the tty instance handled (or that got cancelled), oldest first")]
+    History(HistoryArgs),
+    #[command(
+        about = "Print the effective tty configuration (after merging the config file, env vars and flags) and validate it"
+    )]
+    Config(TtyArgs),
 }
 
 mod tty_about {
+    pub(super) const CONFIG: &str = "Configuration file to read the tty settings from. Defaults to '<XDG config dir>/yt-dlp-wrapper/config.toml' (if present). Command line flags and env vars take priority over the config file.";
+    pub(super) const PROFILE: &str =
+        "Profile of the configuration file to use. Every profile inherits the settings of the 'default' profile.";
+
     pub(super) const YT_DLP_EXEC: &str = "'yt-dlp' executable location.";
     pub(super) const YT_DLP_ARGS: &str = "Extra arguments to pass to '--yt-dlp'. NOTE: '--' will automatically be appended to this. NOTE: each command chain will execute in a different temporary directory.";
 
     pub(super) const BEET_EXEC: &str = "'beet' executable location.";
-    pub(super) const BEET_ARGS: &str = "Extra arguments to pass to '--beet'. '.' will be appended to the command, and the execution directory will be set as the /tmp directory where yt-dlp was executed.";
+    pub(super) const BEET_ARGS: &str = "Extra arguments to pass to '--beet'. '.' will be appended to the command, and the execution directory will be set as the /tmp directory where yt-dlp was executed. When headless, '-q' is appended too and beet gets no stdin.";
 
     pub(super) const FPCALC_EXEC: &str = "'fpcalc' executable location.";
 
@@ -34,68 +62,358 @@ mod tty_about {
 
     pub(super) const MAX_REQUESTS: &str = "Maximum amount of video requests that can be enqueued by request instances (this does not include the request currently being processed). Clamped between 1 and 256, inclusive.";
 
+    pub(super) const WORKERS: &str = "Number of upcoming video requests that get downloaded (and fingerprinted) in the background, each in its own temporary directory, while the current one waits for answers. Their output is shown once they're handled. 0 disables it. Clamped between 0 and 8, inclusive.";
+
     pub(super) const KEEP_TMP: &str =
         "It controls whether or not to keep the tmp directory where the commands are executed.";
 
+    pub(super) const SKIP_FINGERPRINTING: &str =
+        "Skip the fingerprinting (and metadata tagging) stage, yt-dlp's output will be imported as-is.";
+    pub(super) const SKIP_BEET: &str = "Skip the beet import stage. NOTE: unless '--keep-tmp' is used, only the files that yt-dlp writes outside of the temporary directory (e.g. through '-o') will be kept.";
+
+    pub(super) const ALLOWED_HOSTS: &str = "Comma-separated list of hosts (subdomains included) that non-YouTube video requests can point to. YouTube is always allowed.";
+
+    pub(super) const QUEUE_FILE: &str = "File where enqueued video requests are saved, so that they survive tty restarts (and crashes). Unfinished requests found in this file will be restored on startup.";
+
+    pub(super) const RETRY_FAILED: &str = "On startup, enqueue again the video requests whose last attempt (according to the instance's history) failed or got aborted, with their profile and the MusicBrainz recordings already chosen for their files. See 'history retry' to pick them.";
+
     pub(super) const PORT_OVERRIDE: &str = "Use <PORT_OVERRIDE> as the http server's port, instead of using the default behaviour which is to use an OS-provided random port.";
 
+    pub(super) const INSTANCE: &str = "Name of this tty instance. Every instance has its own lockfile (and unix socket), so that several of them can run side by side, e.g. one for music and one for podcasts. Request instances pick one through '--instance'.";
+
+    pub(super) const UNIX_SOCKET: &str = "Serve the http server on a unix socket ('<XDG runtime dir>/yt-dlp-wrapper/tty.sock', only accessible by the current user) instead of a TCP port on 127.0.0.1. Request instances find it through the lockfile.";
+
+    pub(super) const TOKEN: &str = "Token that clients must send ('Authorization: Bearer <token>'), instead of a random one generated on startup. Useful for clients that can't read the lockfile, like browser extensions.";
+
+    pub(super) const CORS_ORIGINS: &str = "Comma-separated list of origins (e.g. 'moz-extension://<uuid>', 'chrome-extension://<id>') allowed to use the json api ('/api/v1') from a browser.";
+
+    pub(super) const HEADLESS: &str = "Never prompt, answer every question with the headless policy instead ('--headless-retries', '--headless-undecided', the autoselect rules) and log the answers. For running the tty without a terminal, e.g. as a systemd user service.";
+
+    pub(super) const DEFER_REVIEW: &str = "Handle video requests unattended (like '--headless') up to the recording selection, and park the ones that can't be autoselected for a later review session ('yt-dlp-wrapper review'), instead of waiting for someone to pick a recording.";
+
+    pub(super) const HEADLESS_RETRIES: &str = "With '--headless', how many times failed commands (and lookups) are retried, per video request, before it's aborted.";
+
+    pub(super) const HEADLESS_UNDECIDED: &str = "With '--headless', what happens to a video request when no MusicBrainz recording could be autoselected: 'park' keeps its temp directory and reports it as needing review, 'abort' aborts it, 'skip' imports the file untagged.";
+
     pub(super) const LOCK_OVERRIDE: &str =
         "ONLY ENABLE THIS IF YOU KNOW WHAT YOU ARE DOING. Enabling this will disable the lockfile and the portfile.";
 }
 
+mod autoselect_about {
+    pub(super) const MIN_SCORE: &str =
+        "AcoustID score (between 0 and 1) an AcoustID match needs to beat to be autoselected.";
+    pub(super) const MIN_SCORE_GAP: &str = "If set, the best AcoustID match will also be autoselected when there are multiple matches, as long as its score beats the runner-up's by at least <MIN_SCORE_GAP>. By default, only lone matches are autoselected.";
+    pub(super) const MAX_DURATION_DELTA: &str = "If set, a MusicBrainz recording can only be autoselected if its length differs from the audio file's by at most <MAX_DURATION_DELTA> seconds. Recordings with an unknown length won't be autoselected.";
+    pub(super) const REQUIRE_ARTIST_MATCH: &str = "Only autoselect MusicBrainz recordings if one of their credited artists appears in the audio file's 'artist' tag (or in its filename, if the tag is missing).";
+    pub(super) const SKIP_CONFIRM: &str =
+        "Skip the final confirmation when the MusicBrainz recording was autoselected.";
+}
+
+// Controls when the AcoustID/MusicBrainz selection tree picks an entry without asking.
+// A recording is autoselected when it's the only one (of the autoselected AcoustID match) that passes all the checks.
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct AutoselectArgs {
+    #[arg(long = "autoselect-min-score", env = "YT_DLP_WRAPPER_AUTOSELECT_MIN_SCORE", default_value = "0.95", value_name = "SCORE", help = autoselect_about::MIN_SCORE)]
+    pub(crate) min_score: f64,
+    #[arg(long = "autoselect-min-score-gap", env = "YT_DLP_WRAPPER_AUTOSELECT_MIN_SCORE_GAP", value_name = "MIN_SCORE_GAP", help = autoselect_about::MIN_SCORE_GAP)]
+    pub(crate) min_score_gap: Option<f64>,
+    #[arg(long = "autoselect-max-duration-delta", env = "YT_DLP_WRAPPER_AUTOSELECT_MAX_DURATION_DELTA", value_name = "MAX_DURATION_DELTA", help = autoselect_about::MAX_DURATION_DELTA)]
+    pub(crate) max_duration_delta: Option<f64>,
+    #[arg(long = "autoselect-require-artist-match", env = "YT_DLP_WRAPPER_AUTOSELECT_REQUIRE_ARTIST_MATCH", help = autoselect_about::REQUIRE_ARTIST_MATCH)]
+    pub(crate) require_artist_match: bool,
+    #[arg(long = "autoselect-skip-confirm", env = "YT_DLP_WRAPPER_AUTOSELECT_SKIP_CONFIRM", help = autoselect_about::SKIP_CONFIRM)]
+    pub(crate) skip_confirm: bool,
+}
+
 #[derive(clap::Args, Debug, Clone)]
 pub(crate) struct TtyArgs {
-    #[arg(long, visible_alias("yt-dlp-executable"), default_value = "yt-dlp", help = tty_about::YT_DLP_EXEC)]
+    #[arg(long, env = "YT_DLP_WRAPPER_CONFIG", help = tty_about::CONFIG)]
+    pub(crate) config: Option<PathBuf>,
+    #[arg(long, env = "YT_DLP_WRAPPER_PROFILE", default_value = crate::config::DEFAULT_PROFILE, help = tty_about::PROFILE)]
+    pub(crate) profile: String,
+    // ids of the args that were given through the command line (or env vars), which the config file can't override
+    #[arg(skip)]
+    pub(crate) explicit_args: HashSet<String>,
+    #[arg(long, env = "YT_DLP_WRAPPER_YT_DLP", visible_alias("yt-dlp-executable"), default_value = "yt-dlp", help = tty_about::YT_DLP_EXEC)]
     pub(crate) yt_dlp: PathBuf,
     #[arg(skip)]
     pub(crate) yt_dlp_display: once_cell::sync::OnceCell<String>,
-    #[arg(long, value_parser = parse_yt_dlp_args, default_value = "", allow_hyphen_values = true, help = tty_about::YT_DLP_ARGS)]
+    #[arg(long, env = "YT_DLP_WRAPPER_YT_DLP_ARGS", value_parser = parse_yt_dlp_args, default_value = "", allow_hyphen_values = true, help = tty_about::YT_DLP_ARGS)]
     pub(crate) yt_dlp_args: PosixSplit,
-    #[arg(long, visible_alias("beet-executable"), default_value = "beet", help = tty_about::BEET_EXEC)]
+    #[arg(long, env = "YT_DLP_WRAPPER_BEET", visible_alias("beet-executable"), default_value = "beet", help = tty_about::BEET_EXEC)]
     pub(crate) beet: PathBuf,
     #[arg(skip)]
     pub(crate) beet_display: once_cell::sync::OnceCell<String>,
-    #[arg(long, value_parser = parse_beet_args, default_value = "import -m -s", allow_hyphen_values = true, help = tty_about::BEET_ARGS)]
+    #[arg(long, env = "YT_DLP_WRAPPER_BEET_ARGS", value_parser = parse_beet_args, default_value = "import -m -s", allow_hyphen_values = true, help = tty_about::BEET_ARGS)]
     pub(crate) beet_args: PosixSplit,
-    #[arg(long, visible_alias("fpcalc-executable"), default_value = "fpcalc", help = tty_about::FPCALC_EXEC)]
+    #[arg(long, env = "YT_DLP_WRAPPER_FPCALC", visible_alias("fpcalc-executable"), default_value = "fpcalc", help = tty_about::FPCALC_EXEC)]
     pub(crate) fpcalc: PathBuf,
     #[arg(skip)]
     pub(crate) fpcalc_display: once_cell::sync::OnceCell<String>,
-    #[arg(long, visible_alias("ffmpeg-executable"), default_value = "ffmpeg", help = tty_about::FFMPEG_EXEC)]
+    #[arg(long, env = "YT_DLP_WRAPPER_FFMPEG", visible_alias("ffmpeg-executable"), default_value = "ffmpeg", help = tty_about::FFMPEG_EXEC)]
     pub(crate) ffmpeg: PathBuf,
     #[arg(skip)]
     pub(crate) ffmpeg_display: once_cell::sync::OnceCell<String>,
-    #[arg(long, default_value = "warning", help = tty_about::FFMPEG_LOGLEVEL)]
+    #[arg(long, env = "YT_DLP_WRAPPER_FFMPEG_LOGLEVEL", default_value = "warning", help = tty_about::FFMPEG_LOGLEVEL)]
     pub(crate) ffmpeg_loglevel: String,
-    #[arg(long, default_value = "16", alias = "max-request", help = tty_about::MAX_REQUESTS)]
+    #[arg(long, env = "YT_DLP_WRAPPER_MAX_REQUESTS", default_value = "16", alias = "max-request", help = tty_about::MAX_REQUESTS)]
     pub(crate) max_requests: u32,
-    #[arg(long, default_value = "never", value_parser = parse_prompt_flag, value_name = "always/ask/never", help = tty_about::KEEP_TMP)]
+    #[arg(long, env = "YT_DLP_WRAPPER_WORKERS", default_value = "0", help = tty_about::WORKERS)]
+    pub(crate) workers: u32,
+    #[arg(long, env = "YT_DLP_WRAPPER_KEEP_TMP", default_value = "never", value_parser = parse_prompt_flag, value_name = "always/ask/never", help = tty_about::KEEP_TMP)]
     pub(crate) keep_tmp: PromptFlag,
-    #[arg(long, help = tty_about::PORT_OVERRIDE)]
+    #[arg(long, env = "YT_DLP_WRAPPER_SKIP_FINGERPRINTING", help = tty_about::SKIP_FINGERPRINTING)]
+    pub(crate) skip_fingerprinting: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_SKIP_BEET", help = tty_about::SKIP_BEET)]
+    pub(crate) skip_beet: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_ALLOWED_HOSTS", value_delimiter = ',', default_value = "bandcamp.com,soundcloud.com", help = tty_about::ALLOWED_HOSTS)]
+    pub(crate) allowed_hosts: Vec<String>,
+    #[arg(long, env = "YT_DLP_WRAPPER_QUEUE_FILE", help = tty_about::QUEUE_FILE)]
+    pub(crate) queue_file: Option<PathBuf>,
+    #[arg(long, env = "YT_DLP_WRAPPER_RETRY_FAILED", help = tty_about::RETRY_FAILED)]
+    pub(crate) retry_failed: bool,
+    #[command(flatten)]
+    pub(crate) autoselect: AutoselectArgs,
+    #[arg(long, env = "YT_DLP_WRAPPER_PORT_OVERRIDE", help = tty_about::PORT_OVERRIDE)]
     pub(crate) port_override: Option<u16>,
+    #[arg(long, env = "YT_DLP_WRAPPER_UNIX_SOCKET", conflicts_with = "port_override", help = tty_about::UNIX_SOCKET)]
+    pub(crate) unix_socket: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_INSTANCE", default_value = crate::lock::DEFAULT_INSTANCE, value_parser = parse_instance_name, help = tty_about::INSTANCE)]
+    pub(crate) instance: String,
+    #[arg(long, env = "YT_DLP_WRAPPER_TOKEN", hide_env_values = true, value_parser = parse_token, help = tty_about::TOKEN)]
+    pub(crate) token: Option<String>,
+    #[arg(long, env = "YT_DLP_WRAPPER_CORS_ORIGINS", value_delimiter = ',', help = tty_about::CORS_ORIGINS)]
+    pub(crate) cors_origins: Vec<String>,
+    #[arg(long, env = "YT_DLP_WRAPPER_HEADLESS", help = tty_about::HEADLESS)]
+    pub(crate) headless: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_DEFER_REVIEW", help = tty_about::DEFER_REVIEW)]
+    pub(crate) defer_review: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_HEADLESS_RETRIES", default_value = "2", help = tty_about::HEADLESS_RETRIES)]
+    pub(crate) headless_retries: u32,
+    #[arg(long, env = "YT_DLP_WRAPPER_HEADLESS_UNDECIDED", default_value = "park", value_parser = parse_undecided_action, value_name = "park/abort/skip", help = tty_about::HEADLESS_UNDECIDED)]
+    pub(crate) headless_undecided: UndecidedAction,
     #[arg(long, help = tty_about::LOCK_OVERRIDE)]
     pub(crate) dangerously_skip_lock_checks: bool,
 }
 
 mod request_about {
-    pub(super) const YT_URL: &str = "Youtube url to use for creating the video request. Supports the majority of modern youtube urls (will extract the ID).";
+    pub(super) const URLS: &str =
+        "Urls to create video requests for, handled like '--url'. '-' reads them from stdin, one per line.";
+
+    pub(super) const YT_URL: &str = "Youtube url to use for creating the video request. Supports the majority of youtube urls, redirects to them and bare video IDs (will extract the ID). Can be repeated.";
+
+    pub(super) const URL: &str = "Url to use for creating the video request, for any site supported by yt-dlp (YouTube urls are handled like '--yt-url'). The host must be allowed by the tty instance ('--allowed-hosts'). Can be repeated.";
+
+    pub(super) const FROM_FILE: &str = "Read urls (handled like '--url') from a file, one per line. Empty lines and lines starting with '#' are ignored, '-' reads from stdin. Can be repeated.";
+
+    pub(super) const PLAYLIST: &str = "Expand the playlist of watch urls that have one ('list' parameter) instead of requesting the single video. Playlist urls (youtube.com/playlist?list=...) are always expanded.";
+
+    pub(super) const PROFILE: &str = "Name of the tty configuration profile to handle the video request with (e.g. to use different yt-dlp args). Defaults to the tty instance's profile.";
+
+    pub(super) const WAIT: &str = "Wait for the video requests to be handled, printing the MusicBrainz recordings and the imported files. Exits with 0 if everything got imported, otherwise with the code of the most severe outcome: 11 (failed), 10 (aborted), 13 (needs review, see the tty's '--headless-undecided'), 12 (cancelled), 15 (unknown, the tty forgot about it), 14 (some urls couldn't be enqueued).";
+
+    pub(super) const PRIORITY: &str = "Enqueue the video requests at the front of the queue, instead of the back.";
 
+    pub(super) const CANCEL: &str = "Remove the pending video request with this id (see the 'queue' subcommand) from the queue, instead of creating new ones. Can be repeated.";
+
+    pub(super) const MOVE_TO_FRONT: &str = "Move the pending video request with this id (see the 'queue' subcommand) to the front of the queue, instead of creating new ones. Can be repeated, the last one ends up first.";
+}
+
+mod connection_about {
     pub(super) const PORT_OVERRIDE: &str =
         "Manually specify the tty instance's http port instead of reading from the lockfile.";
 
-    pub(super) const LOCK_OVERRIDE: &str = "ONLY ENABLE THIS IF YOU KNOW WHAT YOU ARE DOING. Usually request instances will try to tell if the tty instance is running though the lockfile's ownership. This disables that. If this option is active, you NEED to specify a port manually.";
+    pub(super) const TOKEN: &str = "Token to authenticate to the tty instance with, instead of reading it from the lockfile. Only needed when the tty instance runs with '--dangerously-skip-lock-checks' (it prints its token on startup).";
+
+    pub(super) const INSTANCE: &str = "Name of the tty instance to talk to (see the 'instances' subcommand).";
+
+    pub(super) const LOCK_OVERRIDE: &str = "ONLY ENABLE THIS IF YOU KNOW WHAT YOU ARE DOING. Usually request instances will try to tell if the tty instance is running though the lockfile's ownership. This disables that. If this option is active, you NEED to specify a port (and the token) manually.";
 }
 
+// How the subcommands that talk to the tty instance find it.
 #[derive(clap::Args, Debug)]
-pub(crate) struct RequestArgs {
-    #[arg(long, help = request_about::YT_URL)]
-    pub(crate) yt_url: String,
-    #[arg(long, visible_alias("http_port"), help = request_about::PORT_OVERRIDE)]
+pub(crate) struct TtyConnectionArgs {
+    #[arg(long, visible_alias("http_port"), help = connection_about::PORT_OVERRIDE)]
     pub(crate) port_override: Option<u16>,
-    #[arg(long, help = request_about::LOCK_OVERRIDE)]
+    #[arg(long, env = "YT_DLP_WRAPPER_TOKEN", hide_env_values = true, help = connection_about::TOKEN)]
+    pub(crate) token: Option<String>,
+    #[arg(long, env = "YT_DLP_WRAPPER_INSTANCE", default_value = crate::lock::DEFAULT_INSTANCE, value_parser = parse_instance_name, help = connection_about::INSTANCE)]
+    pub(crate) instance: String,
+    #[arg(long, help = connection_about::LOCK_OVERRIDE)]
     pub(crate) dangerously_skip_lock_checks: bool,
 }
 
+#[derive(clap::Args, Debug)]
+#[command(group(
+    clap::ArgGroup::new("source")
+        .required(true)
+        .multiple(true)
+        .args(["urls", "yt_url", "url", "from_file", "cancel", "move_to_front"])
+))]
+pub(crate) struct RequestArgs {
+    #[arg(help = request_about::URLS)]
+    pub(crate) urls: Vec<String>,
+    #[arg(long, help = request_about::YT_URL)]
+    pub(crate) yt_url: Vec<String>,
+    #[arg(long, help = request_about::URL)]
+    pub(crate) url: Vec<String>,
+    #[arg(long, help = request_about::FROM_FILE)]
+    pub(crate) from_file: Vec<PathBuf>,
+    #[arg(long, help = request_about::PLAYLIST)]
+    pub(crate) playlist: bool,
+    #[arg(long, help = request_about::PROFILE)]
+    pub(crate) profile: Option<String>,
+    #[arg(long, help = request_about::WAIT)]
+    pub(crate) wait: bool,
+    #[arg(long, help = request_about::PRIORITY)]
+    pub(crate) priority: bool,
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all(["urls", "yt_url", "url", "from_file", "playlist", "profile", "priority", "wait"]),
+        help = request_about::CANCEL
+    )]
+    pub(crate) cancel: Vec<u64>,
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all(["urls", "yt_url", "url", "from_file", "playlist", "profile", "priority", "wait"]),
+        help = request_about::MOVE_TO_FRONT
+    )]
+    pub(crate) move_to_front: Vec<u64>,
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
+mod queue_about {
+    pub(super) const JSON: &str = "Print the queue as json, as returned by the tty instance.";
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct QueueArgs {
+    #[arg(long, help = queue_about::JSON)]
+    pub(crate) json: bool,
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
+mod instances_about {
+    pub(super) const JSON: &str = "Print the instances as json, as found in their lockfiles (without the token).";
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct InstancesArgs {
+    #[arg(long, help = instances_about::JSON)]
+    pub(crate) json: bool,
+}
+
+mod status_about {
+    pub(super) const JSON: &str = "Print the status as json, as returned by the tty instance.";
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct StatusArgs {
+    #[arg(long, help = status_about::JSON)]
+    pub(crate) json: bool,
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
+mod stop_about {
+    pub(super) const NOW: &str = "Stop right away, without waiting for the current video request. It's saved in the queue file (if any) with the pending ones, and starts over in the next tty session.";
+
+    pub(super) const WAIT: &str = "Wait for the tty instance to exit.";
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct StopArgs {
+    #[arg(long, help = stop_about::NOW)]
+    pub(crate) now: bool,
+    #[arg(long, help = stop_about::WAIT)]
+    pub(crate) wait: bool,
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
+mod review_about {
+    pub(super) const LIST: &str = "List the parked video requests instead of starting a review session.";
+
+    pub(super) const JSON: &str = "List the parked video requests as json, as returned by the tty instance.";
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ReviewArgs {
+    #[arg(long, help = review_about::LIST)]
+    pub(crate) list: bool,
+    #[arg(long, help = review_about::JSON)]
+    pub(crate) json: bool,
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
+mod history_about {
+    pub(super) const SINCE: &str = "Only pick the video requests that finished on or after <SINCE>: a date ('YYYY-MM-DD', UTC) or how long ago (e.g. '12h', '7d').";
+
+    pub(super) const UNTIL: &str = "Only pick the video requests that finished before <UNTIL>: the end of a date ('YYYY-MM-DD', UTC) or how long ago (e.g. '12h', '7d').";
+
+    pub(super) const OUTCOME: &str = "Comma-separated list of outcomes to pick: imported, expanded, aborted, failed, cancelled, needs_review. 'history retry' defaults to 'failed,aborted'.";
+
+    pub(super) const ARTIST: &str = "Only pick the video requests that got tagged with a MusicBrainz recording by <ARTIST> (case-insensitive, partial matches count).";
+
+    pub(super) const JSON: &str = "Print the history entries as json, as written to the history file.";
+
+    pub(super) const INSTANCE: &str =
+        "Name of the tty instance whose history to read. The tty instance doesn't need to be running.";
+
+    pub(super) const PRIORITY: &str = "Enqueue the video requests at the front of the queue, instead of the back.";
+}
+
+// shared by the subcommands that pick entries from the history
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct HistoryFilterArgs {
+    #[arg(long, value_parser = parse_since, help = history_about::SINCE)]
+    pub(crate) since: Option<u64>,
+    #[arg(long, value_parser = parse_until, help = history_about::UNTIL)]
+    pub(crate) until: Option<u64>,
+    #[arg(long, value_delimiter = ',', value_parser = parse_outcome, help = history_about::OUTCOME)]
+    pub(crate) outcome: Vec<Outcome>,
+    #[arg(long, help = history_about::ARTIST)]
+    pub(crate) artist: Option<String>,
+}
+
+// 'history retry' has its own filters (and instance), so that they can't be given to the wrong command
+#[derive(clap::Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub(crate) struct HistoryArgs {
+    #[command(flatten)]
+    pub(crate) filter: HistoryFilterArgs,
+    #[arg(long, help = history_about::JSON)]
+    pub(crate) json: bool,
+    #[arg(long, env = "YT_DLP_WRAPPER_INSTANCE", default_value = crate::lock::DEFAULT_INSTANCE, value_parser = parse_instance_name, help = history_about::INSTANCE)]
+    pub(crate) instance: String,
+    #[command(subcommand)]
+    pub(crate) command: Option<HistoryCommand>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum HistoryCommand {
+    #[command(
+        about = "Enqueue again the video requests whose last attempt failed or got aborted, with their profile and the MusicBrainz recordings already chosen for their files. The tty instance must be running"
+    )]
+    Retry(HistoryRetryArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct HistoryRetryArgs {
+    #[command(flatten)]
+    pub(crate) filter: HistoryFilterArgs,
+    #[arg(long, help = history_about::PRIORITY)]
+    pub(crate) priority: bool,
+    // the history read is the one of the instance the requests are sent to
+    #[command(flatten)]
+    pub(crate) connection: TtyConnectionArgs,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum PromptFlag {
     Always,
@@ -103,7 +421,17 @@ pub(crate) enum PromptFlag {
     Never,
 }
 
-fn parse_prompt_flag(prompt: &str) -> Result<PromptFlag, anyhow::Error> {
+impl Display for PromptFlag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptFlag::Always => write!(f, "always"),
+            PromptFlag::Ask => write!(f, "ask"),
+            PromptFlag::Never => write!(f, "never"),
+        }
+    }
+}
+
+pub(crate) fn parse_prompt_flag(prompt: &str) -> Result<PromptFlag, anyhow::Error> {
     match prompt.to_lowercase().as_str() {
         "always" => Ok(PromptFlag::Always),
         "ask" => Ok(PromptFlag::Ask),
@@ -115,19 +443,115 @@ fn parse_prompt_flag(prompt: &str) -> Result<PromptFlag, anyhow::Error> {
     }
 }
 
-fn parse_yt_dlp_args(args: &str) -> Result<PosixSplit, anyhow::Error> {
+// what a headless tty does when it can't pick a recording on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UndecidedAction {
+    Park,
+    Abort,
+    Skip,
+}
+
+impl Display for UndecidedAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UndecidedAction::Park => write!(f, "park"),
+            UndecidedAction::Abort => write!(f, "abort"),
+            UndecidedAction::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+pub(crate) fn parse_outcome(outcome: &str) -> Result<Outcome, anyhow::Error> {
+    match outcome.to_lowercase().as_str() {
+        "imported" => Ok(Outcome::Imported),
+        "expanded" => Ok(Outcome::Expanded),
+        "aborted" => Ok(Outcome::Aborted),
+        "failed" => Ok(Outcome::Failed),
+        "cancelled" => Ok(Outcome::Cancelled),
+        "needs_review" | "needs-review" => Ok(Outcome::NeedsReview),
+        _ => Err(anyhow!(
+            "Invalid value: '{}', allowed values are 'imported', 'expanded', 'aborted', 'failed', 'cancelled' and 'needs_review'",
+            outcome
+        )),
+    }
+}
+
+// a date is taken as its midnight (UTC), an age as that long before now
+pub(crate) fn parse_since(since: &str) -> Result<u64, anyhow::Error> {
+    if let Some(midnight) = time::parse_date(since) {
+        return Ok(midnight);
+    }
+
+    time::parse_age(since)
+        .map(|age| time::unix_now().saturating_sub(age))
+        .ok_or_else(|| anyhow!("Invalid value: '{since}', expected a date ('YYYY-MM-DD') or an age (e.g. '12h', '7d')"))
+}
+
+// unlike --since, a date includes the whole day
+pub(crate) fn parse_until(until: &str) -> Result<u64, anyhow::Error> {
+    match time::parse_date(until) {
+        Some(midnight) => Ok(midnight + 86400),
+        None => parse_since(until),
+    }
+}
+
+pub(crate) fn parse_undecided_action(action: &str) -> Result<UndecidedAction, anyhow::Error> {
+    match action.to_lowercase().as_str() {
+        "park" => Ok(UndecidedAction::Park),
+        "abort" => Ok(UndecidedAction::Abort),
+        "skip" => Ok(UndecidedAction::Skip),
+        _ => Err(anyhow!(
+            "Invalid value: '{}', allowed values are 'park', 'abort' and 'skip'",
+            action
+        )),
+    }
+}
+
+pub(crate) fn parse_yt_dlp_args(args: &str) -> Result<PosixSplit, anyhow::Error> {
     PosixSplit::from_raw(args).ok_or_else(|| anyhow!("Couldn't parse argument: --yt-dlp-args"))
 }
 
-fn parse_beet_args(args: &str) -> Result<PosixSplit, anyhow::Error> {
+pub(crate) fn parse_beet_args(args: &str) -> Result<PosixSplit, anyhow::Error> {
     PosixSplit::from_raw(args).ok_or_else(|| anyhow!("Couldn't parse argument: --beet-args"))
 }
 
+// it ends up in file names
+pub(crate) fn parse_instance_name(name: &str) -> Result<String, anyhow::Error> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > 64 || !name.chars().all(valid_char) {
+        return Err(anyhow!(
+            "Invalid instance name '{name}', only up to 64 letters, digits, '-' and '_' are allowed"
+        ));
+    }
+
+    Ok(String::from(name))
+}
+
+// it's sent as an http header, and short tokens can be guessed
+pub(crate) fn parse_token(token: &str) -> Result<String, anyhow::Error> {
+    if token.len() < 16 || !token.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(anyhow!(
+            "Invalid token, it must be at least 16 printable ascii characters (no spaces)"
+        ));
+    }
+
+    Ok(String::from(token))
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct PosixSplit {
     pub(crate) components: Vec<String>,
 }
 
+impl TtyArgs {
+    pub(crate) fn init_displays(&self) {
+        self.yt_dlp_display.get_or_init(|| self.yt_dlp.display().to_string());
+        self.beet_display.get_or_init(|| self.beet.display().to_string());
+        self.fpcalc_display.get_or_init(|| self.fpcalc.display().to_string());
+        self.ffmpeg_display.get_or_init(|| self.ffmpeg.display().to_string());
+    }
+}
+
 impl PosixSplit {
     fn new(args: Vec<String>) -> Self {
         Self { components: args }
@@ -136,6 +560,11 @@ impl PosixSplit {
     pub(crate) fn from_raw(raw: &str) -> Option<Self> {
         shlex::split(raw).map(Self::new)
     }
+
+    // inverse of from_raw
+    pub(crate) fn to_raw(&self) -> String {
+        shlex::try_join(self.components.iter().map(String::as_str)).unwrap_or_default()
+    }
 }
 
 impl Display for PosixSplit {
@@ -143,3 +572,64 @@ impl Display for PosixSplit {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_since_and_until() {
+        let midnight = 20743 * 86400;
+        assert_eq!(parse_since("2026-10-17").unwrap(), midnight);
+        assert_eq!(parse_until("2026-10-17").unwrap(), midnight + 86400);
+
+        // ages are relative to now, for both
+        for parse in [parse_since, parse_until] {
+            let before = time::unix_now();
+            let parsed = parse("2h").unwrap();
+            assert!((before - 7200..=time::unix_now() - 7200).contains(&parsed));
+        }
+
+        for invalid in ["2026-02-30", "yesterday", "7w", ""] {
+            assert!(parse_since(invalid).is_err(), "--since accepted {invalid}");
+            assert!(parse_until(invalid).is_err(), "--until accepted {invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_instance_names() {
+        for name in ["default", "music", "pod-casts_2", &"a".repeat(64)] {
+            assert_eq!(parse_instance_name(name).unwrap(), name);
+        }
+
+        // they end up in file names
+        for name in [
+            "",
+            "../default",
+            "music/pop",
+            "with space",
+            "é",
+            ".hidden",
+            &"a".repeat(65),
+        ] {
+            assert!(parse_instance_name(name).is_err(), "accepted '{name}'");
+        }
+    }
+
+    #[test]
+    fn parses_tokens() {
+        for token in ["0123456789abcdef", "!\"#$%&'()*+,-./:;<=>?@[]^_`{|}~"] {
+            assert_eq!(parse_token(token).unwrap(), token);
+        }
+
+        // too short to not be guessed, or not a valid header value
+        for token in [
+            "0123456789abcde",
+            "0123456789 abcdef",
+            "0123456789abcdef\n",
+            "0123456789abcdefé",
+        ] {
+            assert!(parse_token(token).is_err(), "accepted {token:?}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::cli;
+use crate::lock;
+use crate::lock::TtyEndpoint;
+use anyhow::anyhow;
+
+// Talks to the tty instance's http server, for the subcommands that aren't the tty itself.
+pub(crate) struct TtyClient {
+    pub(crate) endpoint: TtyEndpoint,
+    http: reqwest::Client,
+}
+
+impl TtyClient {
+    // finds the tty's port (or unix socket) and token through the lockfile, unless they're manually specified
+    pub(crate) async fn connect(args: &cli::TtyConnectionArgs) -> Result<Self, anyhow::Error> {
+        let skip_lock_checks = args.dangerously_skip_lock_checks;
+        let read_lockfile = || async {
+            let instance = args.instance.clone();
+            tokio::task::spawn_blocking(move || lock::ensure_tty_running_and_read_info(&instance))
+                .await?
+                .map_err(|err| anyhow!("Failed to read the tty lockfile!\n{}", err))
+        };
+
+        let (endpoint, lockfile_token) = match (args.port_override, skip_lock_checks) {
+            (None, false) => {
+                let info = read_lockfile().await?;
+                (info.endpoint, info.token)
+            }
+            (None, true) => {
+                return Err(anyhow!(
+                    "ERROR: The lockfile check is set to be skipped, but no port has been specified!"
+                ));
+            }
+            (Some(port), skip) => {
+                if skip {
+                    println!("WARNING: Skipping lock check!");
+                }
+                println!("Using manually specified port {}", port);
+
+                // the lockfile can still provide the token
+                let lockfile_token = match (&args.token, skip) {
+                    (None, false) => read_lockfile().await.ok().and_then(|info| info.token),
+                    _ => None,
+                };
+                (TtyEndpoint::Port(port), lockfile_token)
+            }
+        };
+
+        let Some(token) = args.token.clone().or(lockfile_token) else {
+            return Err(anyhow!(
+                "Couldn't find the tty's token! Pass it through '--token' (the tty prints it when the lockfile is disabled)"
+            ));
+        };
+        let mut auth_value = reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|err| anyhow!("Invalid tty token!\n{}", err))?;
+        auth_value.set_sensitive(true);
+
+        let mut http = reqwest::Client::builder().default_headers(reqwest::header::HeaderMap::from_iter([(
+            reqwest::header::AUTHORIZATION,
+            auth_value,
+        )]));
+        if let TtyEndpoint::UnixSocket(socket_path) = &endpoint {
+            http = http.unix_socket(socket_path.as_path());
+        }
+        let http = http
+            .build()
+            .map_err(|err| anyhow!("Failed to create http client!\n{}", err))?;
+
+        Ok(Self { endpoint, http })
+    }
+
+    pub(crate) fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.get(self.url(path))
+    }
+
+    pub(crate) fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.post(self.url(path))
+    }
+
+    pub(crate) fn delete(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.delete(self.url(path))
+    }
+
+    fn url(&self, path: &str) -> String {
+        match &self.endpoint {
+            TtyEndpoint::Port(port) => format!("http://127.0.0.1:{port}{path}"),
+            // the host is only used for the Host header, the connection always goes through the socket
+            TtyEndpoint::UnixSocket(_) => format!("http://localhost{path}"),
+        }
+    }
+
+    // turns a non-success response into an error, keeping the tty's message
+    pub(crate) async fn error_for_status(
+        &self,
+        response: reqwest::Response,
+        context: &str,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        Err(anyhow!(
+            "TTY ({}) {context} (http code: {}); {}",
+            self.endpoint,
+            response.status(),
+            response
+                .text()
+                .await
+                .unwrap_or_else(|_| String::from("<unable to decode tty response>")),
+        ))
+    }
+}
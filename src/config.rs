@@ -0,0 +1,544 @@
+use crate::cli;
+use anyhow::anyhow;
+use console::style;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) const DEFAULT_PROFILE: &str = "default";
+
+// Every field mirrors the TtyArgs flag with the same name.
+// Profiles inherit every field they don't set from the 'default' profile.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    #[serde(default)]
+    pub(crate) profiles: BTreeMap<String, ProfileConfig>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProfileConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) yt_dlp: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) yt_dlp_args: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) beet: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) beet_args: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) fpcalc: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ffmpeg: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ffmpeg_loglevel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_requests: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) workers: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_tmp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) skip_fingerprinting: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) skip_beet: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) allowed_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) queue_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) retry_failed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) port_override: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) unix_socket: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cors_origins: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) headless: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) defer_review: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) headless_retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) headless_undecided: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) autoselect: Option<AutoselectConfig>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AutoselectConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min_score_gap: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_duration_delta: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) require_artist_match: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) skip_confirm: Option<bool>,
+}
+
+impl ProfileConfig {
+    // fields set in self win over the ones in fallback
+    fn or(self, fallback: ProfileConfig) -> ProfileConfig {
+        let autoselect = match (self.autoselect, fallback.autoselect) {
+            (Some(autoselect), Some(fallback)) => Some(AutoselectConfig {
+                min_score: autoselect.min_score.or(fallback.min_score),
+                min_score_gap: autoselect.min_score_gap.or(fallback.min_score_gap),
+                max_duration_delta: autoselect.max_duration_delta.or(fallback.max_duration_delta),
+                require_artist_match: autoselect.require_artist_match.or(fallback.require_artist_match),
+                skip_confirm: autoselect.skip_confirm.or(fallback.skip_confirm),
+            }),
+            (autoselect, fallback) => autoselect.or(fallback),
+        };
+
+        ProfileConfig {
+            yt_dlp: self.yt_dlp.or(fallback.yt_dlp),
+            yt_dlp_args: self.yt_dlp_args.or(fallback.yt_dlp_args),
+            beet: self.beet.or(fallback.beet),
+            beet_args: self.beet_args.or(fallback.beet_args),
+            fpcalc: self.fpcalc.or(fallback.fpcalc),
+            ffmpeg: self.ffmpeg.or(fallback.ffmpeg),
+            ffmpeg_loglevel: self.ffmpeg_loglevel.or(fallback.ffmpeg_loglevel),
+            max_requests: self.max_requests.or(fallback.max_requests),
+            workers: self.workers.or(fallback.workers),
+            keep_tmp: self.keep_tmp.or(fallback.keep_tmp),
+            skip_fingerprinting: self.skip_fingerprinting.or(fallback.skip_fingerprinting),
+            skip_beet: self.skip_beet.or(fallback.skip_beet),
+            allowed_hosts: self.allowed_hosts.or(fallback.allowed_hosts),
+            queue_file: self.queue_file.or(fallback.queue_file),
+            retry_failed: self.retry_failed.or(fallback.retry_failed),
+            port_override: self.port_override.or(fallback.port_override),
+            unix_socket: self.unix_socket.or(fallback.unix_socket),
+            instance: self.instance.or(fallback.instance),
+            cors_origins: self.cors_origins.or(fallback.cors_origins),
+            headless: self.headless.or(fallback.headless),
+            defer_review: self.defer_review.or(fallback.defer_review),
+            headless_retries: self.headless_retries.or(fallback.headless_retries),
+            headless_undecided: self.headless_undecided.or(fallback.headless_undecided),
+            autoselect,
+        }
+    }
+
+    pub(crate) fn from_args(args: &cli::TtyArgs) -> ProfileConfig {
+        ProfileConfig {
+            yt_dlp: Some(args.yt_dlp.clone()),
+            yt_dlp_args: Some(args.yt_dlp_args.to_raw()),
+            beet: Some(args.beet.clone()),
+            beet_args: Some(args.beet_args.to_raw()),
+            fpcalc: Some(args.fpcalc.clone()),
+            ffmpeg: Some(args.ffmpeg.clone()),
+            ffmpeg_loglevel: Some(args.ffmpeg_loglevel.clone()),
+            max_requests: Some(args.max_requests),
+            workers: Some(args.workers),
+            keep_tmp: Some(args.keep_tmp.to_string()),
+            skip_fingerprinting: Some(args.skip_fingerprinting),
+            skip_beet: Some(args.skip_beet),
+            allowed_hosts: Some(args.allowed_hosts.clone()),
+            queue_file: args.queue_file.clone(),
+            retry_failed: Some(args.retry_failed),
+            port_override: args.port_override,
+            unix_socket: Some(args.unix_socket),
+            instance: Some(args.instance.clone()),
+            cors_origins: Some(args.cors_origins.clone()),
+            headless: Some(args.headless),
+            defer_review: Some(args.defer_review),
+            headless_retries: Some(args.headless_retries),
+            headless_undecided: Some(args.headless_undecided.to_string()),
+            autoselect: Some(AutoselectConfig {
+                min_score: Some(args.autoselect.min_score),
+                min_score_gap: args.autoselect.min_score_gap,
+                max_duration_delta: args.autoselect.max_duration_delta,
+                require_artist_match: Some(args.autoselect.require_artist_match),
+                skip_confirm: Some(args.autoselect.skip_confirm),
+            }),
+        }
+    }
+}
+
+pub(crate) fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("yt-dlp-wrapper").join("config.toml"))
+}
+
+impl ConfigFile {
+    // an explicitly given config file must exist, the default one is optional
+    pub(crate) fn load(explicit_path: Option<&Path>) -> Result<(ConfigFile, Option<PathBuf>), anyhow::Error> {
+        let path = match explicit_path {
+            Some(path) => path.to_path_buf(),
+            None => match default_config_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok((ConfigFile::default(), None)),
+            },
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| anyhow!("Failed to read config file '{}': {err}", path.display()))?;
+        let config = toml::from_str(&contents)
+            .map_err(|err| anyhow!("Failed to parse config file '{}': {err}", path.display()))?;
+
+        Ok((config, Some(path)))
+    }
+
+    fn profile(&self, name: &str) -> Result<ProfileConfig, anyhow::Error> {
+        let default = self.profiles.get(DEFAULT_PROFILE).cloned().unwrap_or_default();
+
+        if name == DEFAULT_PROFILE {
+            return Ok(default);
+        }
+
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone().or(default)),
+            None => Err(anyhow!("Unknown profile '{name}'")),
+        }
+    }
+
+    // merges the given profile into args, skipping the args that were explicitly given
+    pub(crate) fn apply_profile(&self, args: &cli::TtyArgs, name: &str) -> Result<cli::TtyArgs, anyhow::Error> {
+        let profile = self.profile(name)?;
+        let mut args = args.clone();
+        args.profile = name.to_string();
+
+        let explicit = args.explicit_args.clone();
+        let allowed = |id: &str| !explicit.contains(id);
+
+        if let Some(value) = profile.yt_dlp.filter(|_| allowed("yt_dlp")) {
+            args.yt_dlp = value;
+        }
+        if let Some(value) = profile.yt_dlp_args.filter(|_| allowed("yt_dlp_args")) {
+            args.yt_dlp_args = cli::parse_yt_dlp_args(&value)?;
+        }
+        if let Some(value) = profile.beet.filter(|_| allowed("beet")) {
+            args.beet = value;
+        }
+        if let Some(value) = profile.beet_args.filter(|_| allowed("beet_args")) {
+            args.beet_args = cli::parse_beet_args(&value)?;
+        }
+        if let Some(value) = profile.fpcalc.filter(|_| allowed("fpcalc")) {
+            args.fpcalc = value;
+        }
+        if let Some(value) = profile.ffmpeg.filter(|_| allowed("ffmpeg")) {
+            args.ffmpeg = value;
+        }
+        if let Some(value) = profile.ffmpeg_loglevel.filter(|_| allowed("ffmpeg_loglevel")) {
+            args.ffmpeg_loglevel = value;
+        }
+        if let Some(value) = profile.max_requests.filter(|_| allowed("max_requests")) {
+            args.max_requests = value;
+        }
+        if let Some(value) = profile.workers.filter(|_| allowed("workers")) {
+            args.workers = value;
+        }
+        if let Some(value) = profile.keep_tmp.filter(|_| allowed("keep_tmp")) {
+            args.keep_tmp = cli::parse_prompt_flag(&value)?;
+        }
+        if let Some(value) = profile.skip_fingerprinting.filter(|_| allowed("skip_fingerprinting")) {
+            args.skip_fingerprinting = value;
+        }
+        if let Some(value) = profile.skip_beet.filter(|_| allowed("skip_beet")) {
+            args.skip_beet = value;
+        }
+        if let Some(value) = profile.allowed_hosts.filter(|_| allowed("allowed_hosts")) {
+            args.allowed_hosts = value;
+        }
+        if let Some(value) = profile.queue_file.filter(|_| allowed("queue_file")) {
+            args.queue_file = Some(value);
+        }
+        if let Some(value) = profile.retry_failed.filter(|_| allowed("retry_failed")) {
+            args.retry_failed = value;
+        }
+        if let Some(value) = profile.port_override.filter(|_| allowed("port_override")) {
+            args.port_override = Some(value);
+        }
+        if let Some(value) = profile.unix_socket.filter(|_| allowed("unix_socket")) {
+            args.unix_socket = value;
+        }
+        if let Some(value) = profile.instance.filter(|_| allowed("instance")) {
+            args.instance = cli::parse_instance_name(&value)?;
+        }
+        if let Some(value) = profile.cors_origins.filter(|_| allowed("cors_origins")) {
+            args.cors_origins = value;
+        }
+        if let Some(value) = profile.headless.filter(|_| allowed("headless")) {
+            args.headless = value;
+        }
+        if let Some(value) = profile.defer_review.filter(|_| allowed("defer_review")) {
+            args.defer_review = value;
+        }
+        if let Some(value) = profile.headless_retries.filter(|_| allowed("headless_retries")) {
+            args.headless_retries = value;
+        }
+        if let Some(value) = profile.headless_undecided.filter(|_| allowed("headless_undecided")) {
+            args.headless_undecided = cli::parse_undecided_action(&value)?;
+        }
+
+        let autoselect = profile.autoselect.unwrap_or_default();
+        if let Some(value) = autoselect.min_score.filter(|_| allowed("min_score")) {
+            args.autoselect.min_score = value;
+        }
+        if let Some(value) = autoselect.min_score_gap.filter(|_| allowed("min_score_gap")) {
+            args.autoselect.min_score_gap = Some(value);
+        }
+        if let Some(value) = autoselect.max_duration_delta.filter(|_| allowed("max_duration_delta")) {
+            args.autoselect.max_duration_delta = Some(value);
+        }
+        if let Some(value) = autoselect
+            .require_artist_match
+            .filter(|_| allowed("require_artist_match"))
+        {
+            args.autoselect.require_artist_match = value;
+        }
+        if let Some(value) = autoselect.skip_confirm.filter(|_| allowed("skip_confirm")) {
+            args.autoselect.skip_confirm = value;
+        }
+
+        Ok(args)
+    }
+}
+
+pub(crate) fn explicit_args(matches: &clap::ArgMatches) -> HashSet<String> {
+    use clap::parser::ValueSource;
+
+    matches
+        .ids()
+        .filter(|id| {
+            matches!(
+                matches.value_source(id.as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        })
+        .map(|id| id.to_string())
+        .collect()
+}
+
+// The tty's own profile (selected through --profile) provides the instance-wide settings (port, queue file, ...),
+// and handles the video requests that don't ask for a specific profile.
+pub(crate) struct Profiles {
+    selected: Arc<cli::TtyArgs>,
+    all: BTreeMap<String, Arc<cli::TtyArgs>>,
+}
+
+impl Profiles {
+    pub(crate) fn selected(&self) -> &Arc<cli::TtyArgs> {
+        &self.selected
+    }
+
+    pub(crate) fn get(&self, name: Option<&str>) -> Option<&Arc<cli::TtyArgs>> {
+        match name {
+            None => Some(&self.selected),
+            Some(name) => self.all.get(name),
+        }
+    }
+}
+
+// loads the config file and merges every profile into the command line args
+pub(crate) fn resolve_profiles(args: cli::TtyArgs) -> Result<Profiles, anyhow::Error> {
+    let (config, _path) = ConfigFile::load(args.config.as_deref())?;
+
+    let selected = Arc::new(config.apply_profile(&args, &args.profile)?);
+    selected.init_displays();
+
+    let mut all = BTreeMap::new();
+    all.insert(selected.profile.clone(), selected.clone());
+    for name in config.profiles.keys().chain([&DEFAULT_PROFILE.to_string()]) {
+        if !all.contains_key(name) {
+            let profile_args = config.apply_profile(&args, name)?;
+            profile_args.init_displays();
+            all.insert(name.clone(), Arc::new(profile_args));
+        }
+    }
+
+    Ok(Profiles { selected, all })
+}
+
+// looks for the executable like a shell would
+fn find_executable(executable: &Path) -> Option<PathBuf> {
+    if executable.components().count() > 1 {
+        return executable.is_file().then(|| executable.to_path_buf());
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(executable))
+        .find(|candidate| candidate.is_file())
+}
+
+pub(crate) fn run(args: cli::TtyArgs) -> Result<(), anyhow::Error> {
+    let (config, path) = ConfigFile::load(args.config.as_deref())?;
+
+    let mut problems = Vec::new();
+
+    // every profile must be valid, not just the selected one
+    for name in config.profiles.keys() {
+        if let Err(err) = config.apply_profile(&args, name) {
+            problems.push(format!("profile '{name}': {err}"));
+        }
+    }
+
+    let effective = config.apply_profile(&args, &args.profile)?;
+
+    for (name, executable) in [
+        ("yt_dlp", &effective.yt_dlp),
+        ("beet", &effective.beet),
+        ("fpcalc", &effective.fpcalc),
+        ("ffmpeg", &effective.ffmpeg),
+    ] {
+        if find_executable(executable).is_none() {
+            problems.push(format!("{name}: executable '{}' not found", executable.display()));
+        }
+    }
+
+    match &path {
+        Some(path) => println!("# config file: {}", path.display()),
+        None => println!("# config file: <none>"),
+    }
+    println!("# profile: {}", effective.profile);
+    print!("{}", toml::to_string_pretty(&ProfileConfig::from_args(&effective))?);
+
+    if problems.is_empty() {
+        eprintln!("{}", style("Configuration is valid").for_stderr().green());
+        Ok(())
+    } else {
+        for problem in &problems {
+            eprintln!("{} {problem}", style("ERROR:").for_stderr().red());
+        }
+        Err(anyhow!("Configuration is invalid ({} problem(s))", problems.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    // what tty_args points '--headless-undecided' at when asked to, set to "skip" before the first parse
+    const UNDECIDED_ENV: &str = "YT_DLP_WRAPPER_CONFIG_TESTS_UNDECIDED";
+
+    // like main() does it, but the tests don't depend on the env they run in
+    fn tty_args(flags: &[&str]) -> cli::TtyArgs {
+        parse_tty_args(flags, false)
+    }
+
+    fn parse_tty_args(flags: &[&str], undecided_from_env: bool) -> cli::TtyArgs {
+        static SET_UNDECIDED_ENV: std::sync::Once = std::sync::Once::new();
+        // SAFETY: the variable is private to these tests, and std's env functions (which the rest of the test
+        // binary goes through) are synchronized with set_var
+        SET_UNDECIDED_ENV.call_once(|| unsafe { std::env::set_var(UNDECIDED_ENV, "skip") });
+
+        let command = cli::CliArgs::command().mut_subcommand("tty", |tty| {
+            let ids: Vec<_> = tty.get_arguments().map(|arg| arg.get_id().clone()).collect();
+            let tty = ids
+                .into_iter()
+                .fold(tty, |tty, id| tty.mut_arg(id, |arg| arg.env(None)));
+            match undecided_from_env {
+                true => tty.mut_arg("headless_undecided", |arg| arg.env(UNDECIDED_ENV)),
+                false => tty,
+            }
+        });
+
+        let argv = ["yt-dlp-wrapper", "tty"].iter().chain(flags);
+        let matches = command.try_get_matches_from(argv).unwrap();
+        let explicit = matches
+            .subcommand()
+            .map(|(_, matches)| explicit_args(matches))
+            .unwrap_or_default();
+
+        let cli::Command::Tty(mut args) = cli::CliArgs::from_arg_matches(&matches).unwrap().command else {
+            unreachable!("parsed a tty command");
+        };
+        args.explicit_args = explicit;
+        args
+    }
+
+    const CONFIG: &str = r#"
+        [profiles.default]
+        ffmpeg_loglevel = "error"
+        max_requests = 4
+        headless_retries = 3
+
+        [profiles.default.autoselect]
+        require_artist_match = true
+        min_score = 0.9
+
+        [profiles.music]
+        ffmpeg_loglevel = "info"
+        skip_beet = true
+
+        [profiles.music.autoselect]
+        min_score = 0.5
+    "#;
+
+    #[test]
+    fn profiles_inherit_from_default() {
+        let config: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let args = tty_args(&[]);
+
+        let default = config.apply_profile(&args, DEFAULT_PROFILE).unwrap();
+        assert_eq!(default.ffmpeg_loglevel, "error");
+        assert_eq!(default.max_requests, 4);
+        assert!(!default.skip_beet);
+        assert_eq!(default.autoselect.min_score, 0.9);
+
+        let music = config.apply_profile(&args, "music").unwrap();
+        assert_eq!(music.profile, "music");
+        assert_eq!(music.ffmpeg_loglevel, "info");
+        assert_eq!(music.max_requests, 4);
+        assert!(music.skip_beet);
+        // autoselect tables are merged field by field
+        assert_eq!(music.autoselect.min_score, 0.5);
+        assert!(music.autoselect.require_artist_match);
+    }
+
+    #[test]
+    fn explicit_flags_win_over_every_profile() {
+        let config: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let args = tty_args(&["--ffmpeg-loglevel", "quiet", "--autoselect-min-score", "0.7"]);
+
+        for name in [DEFAULT_PROFILE, "music"] {
+            let profile = config.apply_profile(&args, name).unwrap();
+            assert_eq!(profile.ffmpeg_loglevel, "quiet", "profile {name}");
+            assert_eq!(profile.autoselect.min_score, 0.7, "profile {name}");
+            // flags left at their default value don't count
+            assert_eq!(profile.max_requests, 4, "profile {name}");
+        }
+    }
+
+    #[test]
+    fn env_vars_count_as_explicit() {
+        let args = parse_tty_args(&[], true);
+        assert!(args.explicit_args.contains("headless_undecided"));
+        assert!(!tty_args(&[]).explicit_args.contains("headless_undecided"));
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [profiles.default]
+            headless_undecided = "abort"
+            "#,
+        )
+        .unwrap();
+        let profile = config.apply_profile(&args, DEFAULT_PROFILE).unwrap();
+        assert!(matches!(profile.headless_undecided, cli::UndecidedAction::Skip));
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let config: ConfigFile = toml::from_str(CONFIG).unwrap();
+        assert!(config.apply_profile(&tty_args(&[]), "podcasts").is_err());
+
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [profiles.default]
+            keep_tmp = "sometimes"
+            "#,
+        )
+        .unwrap();
+        assert!(config.apply_profile(&tty_args(&[]), DEFAULT_PROFILE).is_err());
+
+        // typos shouldn't be silently ignored
+        assert!(toml::from_str::<ConfigFile>("[profiles.default]\nmax_request = 4").is_err());
+        assert!(toml::from_str::<ConfigFile>("[profiles.default.autoselect]\nmin_scor = 0.5").is_err());
+    }
+}
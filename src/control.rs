@@ -0,0 +1,266 @@
+use crate::cli;
+use crate::client::TtyClient;
+use crate::events::{self, Event};
+use crate::lock;
+use crate::lock::LockfileContents;
+use crate::net::TtyAxumState;
+use crate::queue::CurrentEntryInfo;
+use crate::utils::time::{format_age, unix_now};
+use console::style;
+use std::path::Path;
+use std::process::{ExitCode, Stdio};
+use std::time::Duration;
+
+// exit codes of the 'status' and 'stop' subcommands, anything else is 1
+const EXIT_NOT_RUNNING: u8 = 3;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct TtyStatus {
+    #[serde(flatten)]
+    pub(crate) tty: LockfileContents,
+    // a graceful stop was requested, the tty exits once the current request is done
+    pub(crate) stopping: bool,
+    pub(crate) current: Option<CurrentEntryInfo>,
+    pub(crate) pending: usize,
+    pub(crate) tools: Vec<ToolVersion>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct ToolVersion {
+    pub(crate) name: String,
+    pub(crate) executable: String,
+    // none if it couldn't be run
+    pub(crate) version: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct StopParams {
+    #[serde(default)]
+    now: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct StopResponse {
+    pub(crate) immediate: bool,
+    pub(crate) pending: usize,
+    // whether the pending requests are saved in the queue file, for the next tty session
+    pub(crate) persisted: bool,
+}
+
+pub(crate) async fn status(axum::extract::State(state): axum::extract::State<TtyAxumState>) -> axum::Json<TtyStatus> {
+    let args = state.profiles.selected();
+    let queue = state.queue.info(&args.profile);
+
+    let (yt_dlp, beet, fpcalc, ffmpeg) = tokio::join!(
+        tool_version("yt-dlp", &args.yt_dlp, "--version"),
+        tool_version("beet", &args.beet, "version"),
+        tool_version("fpcalc", &args.fpcalc, "-version"),
+        tool_version("ffmpeg", &args.ffmpeg, "-version"),
+    );
+
+    axum::Json(TtyStatus {
+        tty: LockfileContents::clone(&state.tty_info),
+        stopping: state.queue.is_stopping(),
+        current: queue.current,
+        pending: queue.pending.len(),
+        tools: vec![yt_dlp, beet, fpcalc, ffmpeg],
+    })
+}
+
+// first line of '<executable> <version_arg>', quietly
+async fn tool_version(name: &str, executable: &Path, version_arg: &str) -> ToolVersion {
+    let output = tokio::process::Command::new(executable)
+        .arg(version_arg)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let version = match tokio::time::timeout(Duration::from_secs(5), output).await {
+        Ok(Ok(output)) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map(|line| line.trim().to_string()),
+        _ => None,
+    };
+
+    ToolVersion {
+        name: String::from(name),
+        executable: executable.display().to_string(),
+        version,
+    }
+}
+
+pub(crate) async fn stop(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+    axum::extract::Query(params): axum::extract::Query<StopParams>,
+) -> (axum::http::StatusCode, axum::Json<StopResponse>) {
+    let pending = state.queue.info(&state.profiles.selected().profile).pending.len();
+    events::emit(Event::Stopping { immediate: params.now });
+
+    let persisted = if params.now {
+        println!("{}", style("Stopping now, as requested").yellow());
+        // the current request is saved too, it'll start over
+        let persisted = state.queue.persist_now();
+        let queue = state.queue.clone();
+        tokio::spawn(async move {
+            // let the response go out first
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            queue.stop_now();
+        });
+        persisted
+    } else {
+        println!(
+            "{}",
+            style("Stopping once the current video request is done, as requested").yellow()
+        );
+        state.queue.stop_after_current();
+        state.queue.persist_now()
+    };
+
+    (
+        axum::http::StatusCode::ACCEPTED,
+        axum::Json(StopResponse {
+            immediate: params.now,
+            pending,
+            persisted,
+        }),
+    )
+}
+
+// None if the tty instance isn't running, so that scripts can tell it apart from other errors
+async fn connect(args: &cli::TtyConnectionArgs) -> Result<Option<TtyClient>, anyhow::Error> {
+    if args.port_override.is_none() && !args.dangerously_skip_lock_checks {
+        let instance = args.instance.clone();
+        if !tokio::task::spawn_blocking(move || lock::is_tty_running(&instance)).await?? {
+            return Ok(None);
+        }
+    }
+
+    TtyClient::connect(args).await.map(Some)
+}
+
+fn not_running(args: &cli::TtyConnectionArgs) -> ExitCode {
+    match args.instance.as_str() {
+        lock::DEFAULT_INSTANCE => println!("{}", style("TTY instance isn't running").yellow()),
+        instance => println!("{}", style(format!("TTY instance '{instance}' isn't running")).yellow()),
+    }
+
+    ExitCode::from(EXIT_NOT_RUNNING)
+}
+
+pub(crate) async fn run_status(args: cli::StatusArgs) -> Result<ExitCode, anyhow::Error> {
+    let Some(client) = connect(&args.connection).await? else {
+        return Ok(not_running(&args.connection));
+    };
+
+    let response = match client.get("/status").timeout(Duration::from_secs(10)).send().await {
+        Ok(response) => response,
+        Err(err) if err.is_connect() => return Ok(not_running(&args.connection)),
+        Err(err) => return Err(err.into()),
+    };
+    let status: TtyStatus = client
+        .error_for_status(response, "couldn't tell its status")
+        .await?
+        .json()
+        .await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let tty = &status.tty;
+    println!(
+        "{} (pid {}, up {})",
+        style(format!("TTY instance '{}' is running", tty.instance)).green(),
+        tty.pid,
+        format_age(unix_now().saturating_sub(tty.started_at))
+    );
+    println!("  Listening on {}", client.endpoint);
+    println!(
+        "  yt-dlp-wrapper {} (protocol {}), profile: {}",
+        tty.version, tty.protocol, tty.profile
+    );
+    match &status.current {
+        Some(current) => println!(
+            "  Current: {} #{} {}",
+            style(current.stage).yellow(),
+            current.entry.id,
+            current.entry.source_url
+        ),
+        None => println!("  Current: none"),
+    }
+    println!("  Pending: {} video request(s)", status.pending);
+    if status.stopping {
+        println!(
+            "  {}",
+            style("Stopping once the current video request is done").yellow()
+        );
+    }
+
+    println!("  Tools:");
+    for tool in &status.tools {
+        match &tool.version {
+            Some(version) => println!("    {}: {version}", tool.name),
+            None => println!("    {}: {} ({})", tool.name, style("not found").red(), tool.executable),
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub(crate) async fn run_stop(args: cli::StopArgs) -> Result<ExitCode, anyhow::Error> {
+    let Some(client) = connect(&args.connection).await? else {
+        return Ok(not_running(&args.connection));
+    };
+
+    let response = match client
+        .post("/stop")
+        .query(&[("now", args.now)])
+        .timeout(Duration::from_secs(1))
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(err) if err.is_connect() => return Ok(not_running(&args.connection)),
+        Err(err) => return Err(err.into()),
+    };
+    let stop: StopResponse = client
+        .error_for_status(response, "refused to stop")
+        .await?
+        .json()
+        .await?;
+
+    if stop.immediate {
+        println!("{} the tty instance", style("Stopped").green());
+    } else {
+        println!(
+            "{} the tty instance once the current video request is done",
+            style("Stopping").green()
+        );
+    }
+    if stop.pending > 0 && !stop.persisted {
+        eprintln!(
+            "{} {} pending video request(s) will be lost, the tty instance has no queue file",
+            style("WARNING:").for_stderr().yellow(),
+            stop.pending
+        );
+    }
+
+    if args.wait && !stop.immediate {
+        // the tty stops answering once it's gone
+        while client
+            .get("/queue")
+            .timeout(Duration::from_secs(1))
+            .send()
+            .await
+            .is_ok()
+        {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        println!("{} the tty instance", style("Stopped").green());
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,85 @@
+use crate::queue::Stage;
+use crate::video::{Outcome, RequestReport};
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+// Subscribers (the `/events` endpoint) that fall behind by this many events start missing some.
+const EVENTS_CAPACITY: usize = 256;
+
+static EVENTS: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(EVENTS_CAPACITY).0);
+
+// What the tty is doing, for other tools to follow without scraping the terminal.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event {
+    Enqueued {
+        id: u64,
+        source_url: String,
+        profile: Option<String>,
+    },
+    RequestStarted {
+        id: u64,
+        source_url: String,
+    },
+    StageStarted {
+        id: u64,
+        stage: Stage,
+    },
+    StageFinished {
+        id: u64,
+        stage: Stage,
+    },
+    CommandStarted {
+        command: Vec<String>,
+    },
+    CommandFinished {
+        command: Vec<String>,
+        // none if the command was killed by a signal
+        exit_code: Option<i32>,
+    },
+    PromptPending {
+        prompt: String,
+    },
+    PromptAnswered,
+    // a headless tty answered a prompt by itself
+    HeadlessAnswer {
+        prompt: String,
+        answer: String,
+    },
+    RequestFinished {
+        id: u64,
+        outcome: Outcome,
+        report: RequestReport,
+    },
+    // immediately, or once the current request is done
+    Stopping {
+        immediate: bool,
+    },
+}
+
+impl Event {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Event::Enqueued { .. } => "enqueued",
+            Event::RequestStarted { .. } => "request_started",
+            Event::StageStarted { .. } => "stage_started",
+            Event::StageFinished { .. } => "stage_finished",
+            Event::CommandStarted { .. } => "command_started",
+            Event::CommandFinished { .. } => "command_finished",
+            Event::PromptPending { .. } => "prompt_pending",
+            Event::PromptAnswered => "prompt_answered",
+            Event::HeadlessAnswer { .. } => "headless_answer",
+            Event::RequestFinished { .. } => "request_finished",
+            Event::Stopping { .. } => "stopping",
+        }
+    }
+}
+
+pub(crate) fn emit(event: Event) {
+    // nobody listening is fine
+    let _ = EVENTS.send(event);
+}
+
+pub(crate) fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
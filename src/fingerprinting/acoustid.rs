@@ -1,6 +1,6 @@
 use crate::fingerprinting::file::FPCalcJsonOutput;
 use crate::musicbrainz::artists_to_string;
-use crate::user::{ask_what_to_do, WhatToDo};
+use crate::user::{self, ask_what_to_do, WhatToDo};
 use crate::utils::iters::IntoRepeatLast;
 use crate::{handle_ctrlc, handle_what_to_do, musicbrainz};
 use console::style;
@@ -23,7 +23,7 @@ pub(crate) mod response {
         pub(crate) results: Option<Vec<LookupResultsEntry>>,
     }
 
-    #[derive(Serialize, Deserialize, Clone)]
+    #[derive(Serialize, Deserialize, Clone, Debug)]
     pub(crate) struct LookupResultsEntry {
         pub(crate) id: String,
         pub(crate) score: f64,
@@ -148,7 +148,7 @@ pub(crate) async fn handle_fingerprint_submission(
     use std::borrow::Cow;
     static ACOUSTID_USER_KEY: OnceCell<String> = OnceCell::new();
 
-    let submit = tokio::task::spawn_blocking(move || {
+    let submit = user::interact("Submit the fingerprint to AcoustID?", move || {
         dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt(format!("{}", style("Would you like to submit the fingerprint?").cyan()))
             .default(true)
@@ -170,7 +170,7 @@ pub(crate) async fn handle_fingerprint_submission(
             } else {
                 // don't set it asap, set it when the request succeeds
                 'api_key: loop {
-                    let user_input = tokio::task::spawn_blocking(move || {
+                    let user_input = user::interact("Insert the AcoustID user API key", move || {
                         dialoguer::Input::<String>::with_theme(&dialoguer::theme::ColorfulTheme::default())
                             .with_prompt("Insert the AcoustID user API key (https://acoustid.org)")
                             .allow_empty(false)
@@ -192,15 +192,18 @@ pub(crate) async fn handle_fingerprint_submission(
             };
 
             let mbid = 'mbid: loop {
-                let maybe_mbid = tokio::task::spawn_blocking(move || {
-                    dialoguer::Input::<String>::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                        .with_prompt(
-                            "Insert the MusicBrainz RECORDING ID that you would like to bind to the fingerprint",
-                        )
-                        .allow_empty(false)
-                        .report(true)
-                        .interact_text()
-                })
+                let maybe_mbid = user::interact(
+                    "Insert the MusicBrainz recording id to bind to the fingerprint",
+                    move || {
+                        dialoguer::Input::<String>::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                            .with_prompt(
+                                "Insert the MusicBrainz RECORDING ID that you would like to bind to the fingerprint",
+                            )
+                            .allow_empty(false)
+                            .report(true)
+                            .interact_text()
+                    },
+                )
                 .await?;
 
                 handle_ctrlc!(
@@ -211,7 +214,7 @@ pub(crate) async fn handle_fingerprint_submission(
                 match maybe_mbid {
                     Ok(value) => {
                         let value2 = value.clone();
-                        let confirm = tokio::task::spawn_blocking(move || {
+                        let confirm = user::interact("Confirm the MusicBrainz recording id", move || {
                             dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
                                 .with_prompt(format!(
                                     "{}: {}",
@@ -394,7 +397,7 @@ pub(crate) async fn confirm_fingerprint_status(
     handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
 
     // just to let the user read
-    let _ignore: String = tokio::task::spawn_blocking(move || {
+    let _ignore: String = user::interact("Press Enter to continue", move || {
         dialoguer::Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt(format!("Press {} to continue...", style("Enter").bold().cyan()))
             .allow_empty(true)
@@ -1,10 +1,32 @@
 use crate::fingerprinting::acoustid;
+use crate::fingerprinting::acoustid::response::LookupResultsEntry;
 use crate::fingerprinting::acoustid::FingerprintSubmissionResult;
-use crate::user::{ask_what_to_do, WhatToDo};
-use crate::{cli, fingerprinting, handle_ctrlc, handle_what_to_do, process};
+use crate::queue::{Stage, VideoRequestQueue};
+use crate::user::{self, ask_what_to_do, WhatToDo};
+use crate::video::{self, NeedsReview, ReportedRecording};
+use crate::{cli, fingerprinting, handle_ctrlc, handle_what_to_do, musicbrainz, process};
 use console::style;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
 use std::path::Path;
+use std::sync::Arc;
+
+// what yt-dlp writes next to the media files (metadata, thumbnails, subtitles, unfinished downloads)
+const SIDECAR_EXTENSIONS: &[&str] = &[
+    "json",
+    "part",
+    "ytdl",
+    "temp",
+    "jpg",
+    "jpeg",
+    "png",
+    "webp",
+    "description",
+    "vtt",
+    "srt",
+    "ass",
+    "lrc",
+];
 
 pub(crate) fn get_fingerprintable_filenames_in_directory(path: &Path) -> Vec<String> {
     let contents = match std::fs::read_dir(path) {
@@ -19,31 +41,62 @@ pub(crate) fn get_fingerprintable_filenames_in_directory(path: &Path) -> Vec<Str
         .collect()
 }
 
+// whether there's anything left for beet to import, besides what yt-dlp wrote next to the media files
+pub(crate) fn has_importable_files(path: &Path) -> bool {
+    get_fingerprintable_filenames_in_directory(path)
+        .iter()
+        .any(|filename| !is_sidecar(filename))
+}
+
+fn is_sidecar(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            SIDECAR_EXTENSIONS
+                .iter()
+                .any(|sidecar| extension.eq_ignore_ascii_case(sidecar))
+        })
+}
+
 pub(crate) async fn handle_fingerprinting_process_for_directory(
     work_dir: &Path,
     acoustid_client: &mut reqwest::Client,
     args: &cli::TtyArgs,
+    vreq_queue: &VideoRequestQueue,
+    recordings: &mut Vec<ReportedRecording>,
+    // by filename, what a background worker already looked up (see prepare::Preparer)
+    prepared: &mut HashMap<String, LookedUpFile>,
+    // by filename, the recordings confirmed by an earlier attempt at the request
+    chosen_recordings: &BTreeMap<String, String>,
 ) -> Result<Option<WhatToDo>, anyhow::Error> {
     let fingerprintable = get_fingerprintable_filenames_in_directory(work_dir);
 
     let mut defaults = vec![true; fingerprintable.len() + 1];
     defaults[0] = false;
 
-    let selections_fingerprintable = fingerprintable.clone();
-    let mut selections = tokio::task::spawn_blocking(move || {
-        dialoguer::MultiSelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
-            .with_prompt(format!(
-                "Select files to fingerprint, if {} is selected, {}",
-                style("<none>").bold(),
-                style("all other selections will be ignored").italic().red()
-            ))
-            .item("<none>")
-            .items(&selections_fingerprintable)
-            .defaults(&defaults)
-            .max_length(16)
-            .interact()
-    })
-    .await??;
+    // 0 is <none>
+    let all_files: Vec<usize> = (1..=fingerprintable.len()).collect();
+    let mut selections = match user::headless_answer("Select the files to fingerprint", all_files, "all") {
+        Some(selections) => selections,
+        None => {
+            let selections_fingerprintable = fingerprintable.clone();
+            user::interact("Select the files to fingerprint", move || {
+                dialoguer::MultiSelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                    .with_prompt(format!(
+                        "Select files to fingerprint, if {} is selected, {}",
+                        style("<none>").bold(),
+                        style("all other selections will be ignored").italic().red()
+                    ))
+                    .item("<none>")
+                    .items(&selections_fingerprintable)
+                    .defaults(&defaults)
+                    .max_length(16)
+                    .interact()
+            })
+            .await??
+        }
+    };
 
     handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
 
@@ -61,30 +114,138 @@ pub(crate) async fn handle_fingerprinting_process_for_directory(
         to_fingerprint.push(&fingerprintable[selection - 1]);
     }
 
+    // unattended, the files that can't be decided on are parked together, once every file got looked up
+    let mut undecided = Vec::new();
     for filename in to_fingerprint {
         let filepath = work_dir.join(filename);
-        if let Some(todo) = handle_fingerprinting_process_for_filepath(&filepath, acoustid_client, args).await? {
-            return Ok(Some(todo));
+
+        // the same video should download to the same files, if it doesn't the choice is simply not used
+        if let Some(recording_id) = chosen_recordings.get(filename.as_str())
+            && let Some(recording) = fetch_chosen_recording(recording_id).await
+        {
+            prepared.remove(filename);
+            match tag_filepath(&filepath, recording, None, false, args, vreq_queue, recordings).await? {
+                FileOutcome::Wtd(todo) => return Ok(Some(todo)),
+                FileOutcome::Done | FileOutcome::Undecided => continue,
+            }
+        }
+
+        let looked_up = match prepared.remove(filename) {
+            Some(looked_up) => looked_up,
+            None => match look_up_filepath(&filepath, acoustid_client, args, vreq_queue).await? {
+                Ok(looked_up) => looked_up,
+                Err(todo) => return Ok(Some(todo)),
+            },
+        };
+
+        match select_and_tag_filepath(&filepath, &looked_up, acoustid_client, args, vreq_queue, recordings).await? {
+            FileOutcome::Done => {}
+            FileOutcome::Wtd(todo) => return Ok(Some(todo)),
+            FileOutcome::Undecided => undecided.push(looked_up),
+        }
+    }
+
+    if !undecided.is_empty() {
+        // the review shouldn't have to wait for MusicBrainz
+        for looked_up in &mut undecided {
+            looked_up.prefetch_recordings().await;
         }
+
+        return Err(NeedsReview {
+            reason: format!("No recording could be autoselected for {} file(s)", undecided.len()),
+            files: undecided,
+        }
+        .into());
     }
 
     Ok(None)
 }
 
-pub(crate) async fn handle_fingerprinting_process_for_filepath(
+// What the selection tree needs to know about a file, gathered without asking anything (unless something fails).
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct LookedUpFile {
+    pub(crate) filename: String,
+    pub(crate) fpcalc_output: FPCalcJsonOutput,
+    // only the AcoustID matches that have recordings
+    pub(crate) results: Vec<LookupResultsEntry>,
+    // empty unless '--autoselect-require-artist-match' is used
+    pub(crate) source_artist: String,
+    // MusicBrainz data of the matches' recordings, only fetched ahead of time for parked requests
+    #[serde(default)]
+    pub(crate) prefetched: Vec<musicbrainz_rs::entity::recording::Recording>,
+}
+
+impl LookedUpFile {
+    // only keeps the AcoustID matches that have recordings, also returns how many were left out
+    pub(crate) async fn new(
+        filepath: &Path,
+        fpcalc_output: FPCalcJsonOutput,
+        results: Vec<LookupResultsEntry>,
+        args: &cli::TtyArgs,
+    ) -> (Self, usize) {
+        let (results_with_recordings, results_others): (Vec<_>, Vec<_>) = results
+            .into_iter()
+            .partition(|entry| entry.recordings.as_ref().is_some_and(|recs| !recs.is_empty()));
+
+        // only the autoselect rules read it, no need to run ffmpeg otherwise
+        let source_artist = match results_with_recordings.is_empty() || !args.autoselect.require_artist_match {
+            true => String::new(),
+            false => read_source_artist(filepath, args).await,
+        };
+
+        let looked_up = LookedUpFile {
+            filename: filepath.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            fpcalc_output,
+            results: results_with_recordings,
+            source_artist,
+            prefetched: Vec::new(),
+        };
+        (looked_up, results_others.len())
+    }
+
+    pub(crate) async fn prefetch_recordings(&mut self) {
+        let mut mbids: Vec<&str> = self
+            .results
+            .iter()
+            .flat_map(|entry| entry.recordings.iter().flatten())
+            .map(|recording| recording.id.as_str())
+            .collect();
+        mbids.sort();
+        mbids.dedup();
+
+        self.prefetched = musicbrainz::fetch_all_recordings_with_interact(mbids)
+            .await
+            .into_iter()
+            .map(Arc::unwrap_or_clone)
+            .collect();
+    }
+}
+
+pub(crate) enum FileOutcome {
+    // tagged, or left as-is
+    Done,
+    Wtd(WhatToDo),
+    // a headless tty couldn't pick a recording, and the policy is to park the request
+    Undecided,
+}
+
+pub(crate) async fn look_up_filepath(
     filepath: &Path,
     acoustid_client: &mut reqwest::Client,
     args: &cli::TtyArgs,
-) -> Result<Option<WhatToDo>, anyhow::Error> {
-    handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
+    vreq_queue: &VideoRequestQueue,
+) -> Result<Result<LookedUpFile, WhatToDo>, anyhow::Error> {
+    vreq_queue.set_stage(Stage::Fingerprinting);
+    handle_ctrlc!(restart: { return Ok(Err(WhatToDo::RestartRequest)) }, abort: { return Ok(Err(WhatToDo::AbortRequest)) });
 
     let fpcalc_output = match fingerprint_filepath(filepath, args).await? {
         Ok(data) => data,
-        Err(todo) => return Ok(Some(todo)),
+        Err(todo) => return Ok(Err(todo)),
     };
 
-    handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
+    handle_ctrlc!(restart: { return Ok(Err(WhatToDo::RestartRequest)) }, abort: { return Ok(Err(WhatToDo::AbortRequest)) });
 
+    vreq_queue.set_stage(Stage::Lookup);
     let fingerprint_lookup = 'lookup: loop {
         let lookup = acoustid::lookup_fingerprint(
             acoustid_client,
@@ -94,7 +255,7 @@ pub(crate) async fn handle_fingerprinting_process_for_filepath(
         )
         .await?;
 
-        handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
+        handle_ctrlc!(restart: { return Ok(Err(WhatToDo::RestartRequest)) }, abort: { return Ok(Err(WhatToDo::AbortRequest)) });
 
         match lookup.status.as_ref() {
             "ok" => break 'lookup Some(lookup),
@@ -110,9 +271,9 @@ pub(crate) async fn handle_fingerprinting_process_for_filepath(
 
                 handle_what_to_do!(what_to_do, [
                     retry: { continue 'lookup },
-                    restart: { return Ok(Some(WhatToDo::RestartRequest)) },
+                    restart: { return Ok(Err(WhatToDo::RestartRequest)) },
                     cont: { break 'lookup None },
-                    abort: { return Ok(Some(WhatToDo::AbortRequest)) }
+                    abort: { return Ok(Err(WhatToDo::AbortRequest)) }
                 ]);
             }
         }
@@ -123,36 +284,91 @@ pub(crate) async fn handle_fingerprinting_process_for_filepath(
         .and_then(|lookup| lookup.results)
         .unwrap_or_else(Vec::new);
 
-    // ...which will make both of these empty too...
-    let (results_with_recordings, results_others): (Vec<_>, Vec<_>) = results
-        .into_iter()
-        .partition(|entry| entry.recordings.as_ref().is_some_and(|recs| !recs.is_empty()));
+    // ...which will leave the file without matches
+    let (looked_up, ignored) = LookedUpFile::new(filepath, fpcalc_output, results, args).await;
 
-    if !results_others.is_empty() {
+    if ignored > 0 {
         println!(
             "{}",
             style(format!(
                 "Ignoring {} matches that do not have any associated recordings!",
-                results_others.len()
+                ignored
             ))
             .yellow()
         );
     }
 
-    let mut selection = if results_with_recordings.is_empty() {
+    Ok(Ok(looked_up))
+}
+
+pub(crate) async fn select_and_tag_filepath(
+    filepath: &Path,
+    looked_up: &LookedUpFile,
+    acoustid_client: &mut reqwest::Client,
+    args: &cli::TtyArgs,
+    vreq_queue: &VideoRequestQueue,
+    recordings: &mut Vec<ReportedRecording>,
+) -> Result<FileOutcome, anyhow::Error> {
+    let mut selection = if looked_up.results.is_empty() {
         println!("{}", style("No AcoustID matches with associated recordings!").magenta());
         None
     } else {
-        fingerprinting::get_recording_from_selection_tree(&results_with_recordings).await?
-    };
+        let context = fingerprinting::AutoselectContext {
+            duration: looked_up.fpcalc_output.duration,
+            source_artist: looked_up.source_artist.clone(),
+        };
 
-    handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
+        fingerprinting::get_recording_from_selection_tree(
+            &looked_up.results,
+            &looked_up.prefetched,
+            &args.autoselect,
+            &context,
+        )
+        .await?
+    };
 
-    if selection.is_none() {
+    handle_ctrlc!(restart: { return Ok(FileOutcome::Wtd(WhatToDo::RestartRequest)) }, abort: { return Ok(FileOutcome::Wtd(WhatToDo::AbortRequest)) });
+
+    let mut fingerprint_submitted = false;
+    if selection.is_none()
+        && let Some(policy) = user::headless()
+    {
+        // submitting a fingerprint needs someone to find the right recording
+        let filename = &looked_up.filename;
+        match policy.undecided {
+            cli::UndecidedAction::Park => {
+                println!(
+                    "{}",
+                    style(format!(
+                        "No recording could be autoselected for '{filename}', parking it"
+                    ))
+                    .yellow()
+                );
+                return Ok(FileOutcome::Undecided);
+            }
+            cli::UndecidedAction::Abort => {
+                println!(
+                    "{}",
+                    style(format!("No recording could be autoselected for '{filename}', aborting")).yellow()
+                );
+                return Ok(FileOutcome::Wtd(WhatToDo::AbortRequest));
+            }
+            cli::UndecidedAction::Skip => {
+                println!(
+                    "{}",
+                    style(format!(
+                        "No recording could be autoselected for '{filename}', leaving it untagged"
+                    ))
+                    .yellow()
+                );
+            }
+        }
+    } else if selection.is_none() {
         // ...which will trigger this
-        match acoustid::handle_fingerprint_submission(acoustid_client, &fpcalc_output).await? {
-            FingerprintSubmissionResult::Wtd(what_to_do) => return Ok(Some(what_to_do)),
+        match acoustid::handle_fingerprint_submission(acoustid_client, &looked_up.fpcalc_output).await? {
+            FingerprintSubmissionResult::Wtd(what_to_do) => return Ok(FileOutcome::Wtd(what_to_do)),
             FingerprintSubmissionResult::Recording(recording) => {
+                fingerprint_submitted = true;
                 selection.replace(recording);
             }
             FingerprintSubmissionResult::Nothing => {
@@ -161,19 +377,192 @@ pub(crate) async fn handle_fingerprinting_process_for_filepath(
         };
     }
 
-    handle_ctrlc!(restart: { return Ok(Some(WhatToDo::RestartRequest)) }, abort: { return Ok(Some(WhatToDo::AbortRequest)) });
+    handle_ctrlc!(restart: { return Ok(FileOutcome::Wtd(WhatToDo::RestartRequest)) }, abort: { return Ok(FileOutcome::Wtd(WhatToDo::AbortRequest)) });
 
     match selection {
-        None => Ok(None),
+        None => Ok(FileOutcome::Done),
         Some(recording) => {
-            let maybe_what_to_do =
-                fingerprinting::metadata::ffmpeg_modify_metadata_to_match_recording(filepath, recording, args).await?;
-            Ok(maybe_what_to_do)
+            let acoustid_id = looked_up
+                .results
+                .iter()
+                .find(|entry| {
+                    entry
+                        .recordings
+                        .iter()
+                        .flatten()
+                        .any(|entry_recording| entry_recording.id == recording.id)
+                })
+                .map(|entry| entry.id.clone());
+
+            tag_filepath(
+                filepath,
+                recording,
+                acoustid_id,
+                fingerprint_submitted,
+                args,
+                vreq_queue,
+                recordings,
+            )
+            .await
+        }
+    }
+}
+
+// None if it couldn't be fetched, the file then goes through the lookup like any other
+async fn fetch_chosen_recording(recording_id: &str) -> Option<Arc<musicbrainz_rs::entity::recording::Recording>> {
+    match musicbrainz::fetch_recording_data(recording_id).await {
+        Ok(recording) => {
+            println!(
+                "{} '{}' by {}",
+                style("Using the recording chosen before:").green(),
+                recording.title,
+                recording
+                    .artist_credit
+                    .as_ref()
+                    .map(musicbrainz::artists_to_string)
+                    .unwrap_or_default()
+                    .trim_end()
+            );
+            Some(recording)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} https://musicbrainz.org/recording/{recording_id}, looking the file up instead: {err}",
+                style("Failed to fetch the recording chosen before")
+                    .for_stderr()
+                    .yellow()
+            );
+            None
+        }
+    }
+}
+
+async fn tag_filepath(
+    filepath: &Path,
+    recording: Arc<musicbrainz_rs::entity::recording::Recording>,
+    acoustid_id: Option<String>,
+    fingerprint_submitted: bool,
+    args: &cli::TtyArgs,
+    vreq_queue: &VideoRequestQueue,
+    recordings: &mut Vec<ReportedRecording>,
+) -> Result<FileOutcome, anyhow::Error> {
+    if !args.skip_beet && skip_already_imported(filepath, &recording, args).await? {
+        return Ok(FileOutcome::Done);
+    }
+
+    let reported = ReportedRecording {
+        id: recording.id.clone(),
+        title: recording.title.clone(),
+        artist: recording
+            .artist_credit
+            .as_ref()
+            .map(musicbrainz::artists_to_string)
+            .unwrap_or_default(),
+        acoustid_id,
+        fingerprint_submitted,
+        file: filepath
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .map(ToString::to_string),
+    };
+
+    vreq_queue.set_stage(Stage::Tagging);
+    let maybe_what_to_do =
+        fingerprinting::metadata::ffmpeg_modify_metadata_to_match_recording(filepath, recording, args).await?;
+    match maybe_what_to_do {
+        Some(what_to_do) => Ok(FileOutcome::Wtd(what_to_do)),
+        None => {
+            recordings.push(reported);
+            Ok(FileOutcome::Done)
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+// offers to leave the file out of the beet import (by removing it) when the recording is already in the library
+async fn skip_already_imported(
+    filepath: &Path,
+    recording: &musicbrainz_rs::entity::recording::Recording,
+    args: &cli::TtyArgs,
+) -> Result<bool, anyhow::Error> {
+    let Some(paths) = video::library_paths(args, &recording.id)
+        .await
+        .filter(|paths| !paths.is_empty())
+    else {
+        return Ok(false);
+    };
+
+    println!(
+        "{} '{}' ({}) is already in the beets library:",
+        style("WARNING:").yellow(),
+        recording.title,
+        recording.id
+    );
+    for path in &paths {
+        println!("  {}", path.display());
+    }
+
+    let filename = filepath.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let skip = match user::headless_answer("Skip importing the file?", true, "yes") {
+        Some(skip) => skip,
+        None => {
+            let filename = filename.clone();
+            user::interact("Skip importing the file?", move || {
+                dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                    .with_prompt(format!(
+                        "Would you like to {} importing '{}'?",
+                        style("skip").yellow(),
+                        filename
+                    ))
+                    .default(true)
+                    .show_default(true)
+                    .wait_for_newline(true)
+                    .interact()
+            })
+            .await??
+        }
+    };
+
+    if skip {
+        std::fs::remove_file(filepath)?;
+        println!("{}", style(format!("Left '{filename}' out of the import")).magenta());
+    }
+
+    Ok(skip)
+}
+
+// reads the 'artist' tag through ffmpeg, falling back to the filename
+pub(crate) async fn read_source_artist(filepath: &Path, args: &cli::TtyArgs) -> String {
+    let filename = || {
+        filepath
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    // no need to enter a command context for this, if it fails we just use the filename
+    let output = tokio::process::Command::new(args.ffmpeg_display.get().unwrap())
+        .args(["-loglevel", "error", "-i"])
+        .arg(filepath)
+        .args(["-f", "ffmetadata", "-"])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => return filename(),
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, value)| key.eq_ignore_ascii_case("artist") && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_else(filename)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub(crate) struct FPCalcJsonOutput {
     pub(crate) duration: f64,
     pub(crate) fingerprint: String,
@@ -234,3 +623,48 @@ pub(crate) async fn fingerprint_filepath(
 
     Ok(Ok(fpcalc_output))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_only_media_files() {
+        let cases: &[(&[&str], bool)] = &[
+            (&[], false),
+            (&["Song [dQw4w9WgXcQ].info.json", "Song [dQw4w9WgXcQ].webp"], false),
+            (
+                &[
+                    "Song.opus.part",
+                    "Song.ytdl",
+                    "Song.description",
+                    "Song.en.vtt",
+                    "Song.LRC",
+                ],
+                false,
+            ),
+            (&["Song [dQw4w9WgXcQ].opus", "Song [dQw4w9WgXcQ].info.json"], true),
+            (&["Song.m4a"], true),
+            (&["Song.flac", "cover.JPG"], true),
+            // beet decides what to do with the files it doesn't know about
+            (&["notes"], true),
+        ];
+
+        for (filenames, importable) in cases {
+            let work_dir = tempfile::tempdir().unwrap();
+            for filename in *filenames {
+                std::fs::write(work_dir.path().join(filename), b"").unwrap();
+            }
+            // only files count
+            std::fs::create_dir(work_dir.path().join("thumbnails")).unwrap();
+
+            assert_eq!(has_importable_files(work_dir.path()), *importable, "{filenames:?}");
+            // every file still gets fingerprinted
+            assert_eq!(
+                get_fingerprintable_filenames_in_directory(work_dir.path()).len(),
+                filenames.len(),
+                "{filenames:?}"
+            );
+        }
+    }
+}
@@ -3,21 +3,24 @@ pub(crate) mod file;
 pub(crate) mod metadata;
 
 use crate::fingerprinting::acoustid::response::LookupResultsEntry;
-use crate::musicbrainz;
+use crate::{cli, musicbrainz, user};
 use console::style;
 use std::future::ready;
 use std::sync::Arc;
 
 struct SelectionTreeLookupResultsEntry<'lre> {
     entry: &'lre LookupResultsEntry,
+    // recordings fetched ahead of time (for parked requests), the others are fetched when needed
+    prefetched: &'lre [musicbrainz_rs::entity::recording::Recording],
     recording_data: tokio::sync::OnceCell<Vec<Arc<musicbrainz_rs::entity::recording::Recording>>>,
     entry_display: String,
     recording_display: tokio::sync::OnceCell<Arc<Vec<String>>>,
 }
 
 impl<'lre> SelectionTreeLookupResultsEntry<'lre> {
-    fn new(entry: &'lre LookupResultsEntry) -> Self {
+    fn new(entry: &'lre LookupResultsEntry, prefetched: &'lre [musicbrainz_rs::entity::recording::Recording]) -> Self {
         Self {
+            prefetched,
             recording_data: tokio::sync::OnceCell::new(),
             entry_display: format!(
                 "Score: {}, AcoustID: {}, Recordings: {}",
@@ -35,10 +38,25 @@ impl<'lre> SelectionTreeLookupResultsEntry<'lre> {
             .get_or_init(|| async {
                 match self.entry.recordings.as_ref() {
                     Some(vec) => {
-                        musicbrainz::fetch_all_recordings_with_interact(
-                            vec.iter().map(|entry| &entry.id).collect::<Vec<_>>(),
-                        )
-                        .await
+                        let prefetched: Option<Vec<_>> = vec
+                            .iter()
+                            .map(|entry| {
+                                self.prefetched
+                                    .iter()
+                                    .find(|recording| recording.id == entry.id)
+                                    .map(|recording| Arc::new(recording.clone()))
+                            })
+                            .collect();
+
+                        match prefetched {
+                            Some(recordings) => recordings,
+                            None => {
+                                musicbrainz::fetch_all_recordings_with_interact(
+                                    vec.iter().map(|entry| &entry.id).collect::<Vec<_>>(),
+                                )
+                                .await
+                            }
+                        }
                     }
                     None => ready(Vec::new()).await,
                 }
@@ -84,69 +102,185 @@ impl<'lre> SelectionTreeLookupResultsEntry<'lre> {
     }
 }
 
+// what we know about the audio file, used by the autoselect rules
+pub(crate) struct AutoselectContext {
+    pub(crate) duration: f64,
+    // the file's 'artist' tag, or its filename if missing
+    pub(crate) source_artist: String,
+}
+
+mod autoselect {
+    use super::*;
+
+    pub(super) fn top_level_index(
+        rules: &cli::AutoselectArgs,
+        results: &[SelectionTreeLookupResultsEntry],
+    ) -> Option<usize> {
+        let (best_index, best) = results
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.entry.score.total_cmp(&b.entry.score))?;
+
+        // strictly above, like it was before the threshold could be configured
+        if best.entry.score <= rules.min_score {
+            return None;
+        }
+
+        if results.len() == 1 {
+            return Some(best_index);
+        }
+
+        let runner_up = results
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != best_index)
+            .map(|(_, result)| result.entry.score)
+            .max_by(f64::total_cmp)?;
+
+        rules
+            .min_score_gap
+            .filter(|gap| best.entry.score - runner_up >= *gap)
+            .map(|_| best_index)
+    }
+
+    pub(super) fn recording_index(
+        rules: &cli::AutoselectArgs,
+        context: &AutoselectContext,
+        recordings: &[Arc<musicbrainz_rs::entity::recording::Recording>],
+    ) -> Option<usize> {
+        let mut candidates = recordings
+            .iter()
+            .enumerate()
+            .filter(|(_, recording)| matches_duration(rules, context, recording))
+            .filter(|(_, recording)| matches_artist(rules, context, recording))
+            .map(|(index, _)| index);
+
+        match (candidates.next(), candidates.next()) {
+            (Some(index), None) => Some(index),
+            _ => None,
+        }
+    }
+
+    fn matches_duration(
+        rules: &cli::AutoselectArgs,
+        context: &AutoselectContext,
+        recording: &musicbrainz_rs::entity::recording::Recording,
+    ) -> bool {
+        match (rules.max_duration_delta, recording.length) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(max_delta), Some(length_ms)) => (length_ms as f64 / 1000.0 - context.duration).abs() <= max_delta,
+        }
+    }
+
+    fn matches_artist(
+        rules: &cli::AutoselectArgs,
+        context: &AutoselectContext,
+        recording: &musicbrainz_rs::entity::recording::Recording,
+    ) -> bool {
+        if !rules.require_artist_match {
+            return true;
+        }
+
+        let source_artist = context.source_artist.to_lowercase();
+        recording.artist_credit.as_ref().is_some_and(|credits| {
+            credits
+                .iter()
+                .any(|credit| !credit.name.is_empty() && source_artist.contains(&credit.name.to_lowercase()))
+        })
+    }
+}
+
 mod tree {
     use super::*;
 
     pub(super) async fn ask_top_level<'lre>(
         first_run: bool,
+        rules: &cli::AutoselectArgs,
         results: &'lre [SelectionTreeLookupResultsEntry<'lre>],
         results_display: Arc<Vec<String>>,
     ) -> Result<Option<&'lre SelectionTreeLookupResultsEntry<'lre>>, tokio::task::JoinError> {
-        if first_run && results.len() == 1 && results[0].entry.score > 0.95 {
-            println!("{} {}", style("Autoselecting").magenta(), &results_display[0]);
-            Ok(Some(&results[0]))
+        let autoselected = first_run.then(|| autoselect::top_level_index(rules, results)).flatten();
+
+        if let Some(index) = autoselected {
+            println!("{} {}", style("Autoselecting").magenta(), &results_display[index]);
+            Ok(Some(&results[index]))
         } else {
-            let selected = tokio::task::spawn_blocking(move || {
-                dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                    .item("<none>")
-                    .items(&results_display)
-                    .default(0)
-                    .max_length(16)
-                    .interact()
-                    .ok()
-                    .filter(|index| *index > 0)
-            })
-            .await?;
+            let selected =
+                match user::headless_answer("Select an AcoustID match", None, "<none> (nothing to autoselect)") {
+                    Some(selected) => selected,
+                    None => {
+                        user::interact("Select an AcoustID match", move || {
+                            dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                                .item("<none>")
+                                .items(&results_display)
+                                .default(0)
+                                .max_length(16)
+                                .interact()
+                                .ok()
+                                .filter(|index| *index > 0)
+                        })
+                        .await?
+                    }
+                };
 
             Ok(selected.map(|index| &results[index - 1]))
         }
     }
 
+    // the bool is true when the recording was autoselected
     pub(super) async fn ask_results<'lre>(
         first_run: bool,
+        rules: &cli::AutoselectArgs,
+        context: &AutoselectContext,
         entry: &'lre SelectionTreeLookupResultsEntry<'lre>,
-    ) -> Result<Option<Arc<musicbrainz_rs::entity::recording::Recording>>, tokio::task::JoinError> {
+    ) -> Result<Option<(Arc<musicbrainz_rs::entity::recording::Recording>, bool)>, tokio::task::JoinError> {
         let recordings = entry.recording_data().await;
         let recordings_display = entry.recording_display().await;
 
-        if first_run && recordings.len() == 1 {
-            println!("{} {}", style("Autoselecting").magenta(), recordings_display[0]);
-            Ok(Some(recordings[0].clone()))
+        let autoselected = first_run
+            .then(|| autoselect::recording_index(rules, context, recordings))
+            .flatten();
+
+        if let Some(index) = autoselected {
+            println!("{} {}", style("Autoselecting").magenta(), recordings_display[index]);
+            Ok(Some((recordings[index].clone(), true)))
         } else {
-            let id = entry.entry.id.clone();
-            let selected = tokio::task::spawn_blocking(move || {
-                dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                    .with_prompt(format!("{}: {}", style("Currently exploring AcoustID").italic(), id))
-                    .item("<back>")
-                    .items(&recordings_display)
-                    .default(0)
-                    .max_length(16)
-                    .interact()
-                    .ok()
-                    .filter(|index| *index > 0)
-            })
-            .await?;
+            let selected =
+                match user::headless_answer("Select a MusicBrainz recording", None, "<back> (nothing to autoselect)") {
+                    Some(selected) => selected,
+                    None => {
+                        let id = entry.entry.id.clone();
+                        user::interact("Select a MusicBrainz recording", move || {
+                            dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                                .with_prompt(format!("{}: {}", style("Currently exploring AcoustID").italic(), id))
+                                .item("<back>")
+                                .items(&recordings_display)
+                                .default(0)
+                                .max_length(16)
+                                .interact()
+                                .ok()
+                                .filter(|index| *index > 0)
+                        })
+                        .await?
+                    }
+                };
 
-            Ok(selected.map(|index| recordings[index - 1].clone()))
+            Ok(selected.map(|index| (recordings[index - 1].clone(), false)))
         }
     }
 }
 
 async fn get_recording_from_selection_tree(
     results: &[LookupResultsEntry],
+    prefetched: &[musicbrainz_rs::entity::recording::Recording],
+    rules: &cli::AutoselectArgs,
+    context: &AutoselectContext,
 ) -> Result<Option<Arc<musicbrainz_rs::entity::recording::Recording>>, anyhow::Error> {
-    let results: Vec<SelectionTreeLookupResultsEntry> =
-        results.iter().map(SelectionTreeLookupResultsEntry::new).collect();
+    let results: Vec<SelectionTreeLookupResultsEntry> = results
+        .iter()
+        .map(|entry| SelectionTreeLookupResultsEntry::new(entry, prefetched))
+        .collect();
 
     let results_display: Arc<Vec<String>> = Arc::new(
         results
@@ -162,14 +296,14 @@ async fn get_recording_from_selection_tree(
 
     let mut first_run = true;
     'outer: loop {
-        match tree::ask_top_level(first_run, &results, results_display.clone()).await? {
+        match tree::ask_top_level(first_run, rules, &results, results_display.clone()).await? {
             Some(entry) => 'inner: loop {
-                match tree::ask_results(first_run, entry).await? {
+                match tree::ask_results(first_run, rules, context, entry).await? {
                     None => {
                         first_run = false;
                         continue 'outer;
                     }
-                    Some(record) => {
+                    Some((record, autoselected)) => {
                         let empty_string = String::new();
                         println!(
                             "\n{}\nRecording: https://musicbrainz.org/recording/{}\nTitle: {}\nDisambiguation: {}\nArtists: {}\n",
@@ -189,7 +323,12 @@ async fn get_recording_from_selection_tree(
                             .cyan()
                             .bold(),
                         );
-                        let confirm = tokio::task::spawn_blocking(move || {
+                        if autoselected && (rules.skip_confirm || user::headless().is_some()) {
+                            println!("{}", style("Autoselected, skipping confirmation").magenta());
+                            return Ok(Some(record));
+                        }
+
+                        let confirm = user::interact("Confirm the selected recording", move || {
                             dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
                                 .with_prompt("Confirm?")
                                 .default(true)
@@ -213,3 +352,67 @@ async fn get_recording_from_selection_tree(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acoustid::response::LookupResultsEntry;
+    use clap::Parser;
+
+    #[derive(clap::Parser)]
+    struct Rules {
+        #[command(flatten)]
+        autoselect: cli::AutoselectArgs,
+    }
+
+    fn rules(flags: &[&str]) -> cli::AutoselectArgs {
+        Rules::try_parse_from(std::iter::once("rules").chain(flags.iter().copied()))
+            .unwrap()
+            .autoselect
+    }
+
+    fn entry(score: f64) -> LookupResultsEntry {
+        LookupResultsEntry {
+            id: format!("match-{score}"),
+            score,
+            recordings: None,
+        }
+    }
+
+    fn top_level_index(rules: &cli::AutoselectArgs, scores: &[f64]) -> Option<usize> {
+        let entries: Vec<_> = scores.iter().map(|&score| entry(score)).collect();
+        let results: Vec<_> = entries
+            .iter()
+            .map(|entry| SelectionTreeLookupResultsEntry {
+                entry,
+                prefetched: &[],
+                recording_data: tokio::sync::OnceCell::new(),
+                entry_display: String::new(),
+                recording_display: tokio::sync::OnceCell::new(),
+            })
+            .collect();
+        autoselect::top_level_index(rules, &results)
+    }
+
+    #[test]
+    fn autoselects_matches_above_the_min_score() {
+        let default = rules(&[]);
+        let with_gap = rules(&["--autoselect-min-score", "0.8", "--autoselect-min-score-gap", "0.1"]);
+        let cases: &[(&cli::AutoselectArgs, &[f64], Option<usize>)] = &[
+            (&default, &[], None),
+            (&default, &[0.96], Some(0)),
+            // the min score itself isn't enough
+            (&default, &[0.95], None),
+            (&default, &[0.5], None),
+            // several matches need a gap
+            (&default, &[0.99, 0.5], None),
+            (&with_gap, &[0.5, 0.99], Some(1)),
+            (&with_gap, &[0.8, 0.5], None),
+            (&with_gap, &[0.95, 0.9], None),
+        ];
+
+        for (rules, scores, expected) in cases {
+            assert_eq!(top_level_index(rules, scores), *expected, "{scores:?}");
+        }
+    }
+}
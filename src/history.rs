@@ -0,0 +1,316 @@
+use crate::cli;
+use crate::client::TtyClient;
+use crate::queue::{StageTiming, VideoRequestQueue};
+use crate::request;
+use crate::utils::time::format_utc;
+use crate::video::{Outcome, RequestReport, VideoRequest};
+use anyhow::anyhow;
+use console::style;
+use std::collections::HashSet;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+// One json line per finished (or cancelled) video request, appended by the tty once it's done with it.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct HistoryEntry {
+    // only unique within a tty session
+    pub(crate) id: u64,
+    pub(crate) source_url: String,
+    #[serde(flatten)]
+    pub(crate) request: VideoRequest,
+    // unix timestamps (seconds), started_at is missing for requests that were cancelled before being handled
+    pub(crate) enqueued_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) started_at: Option<u64>,
+    pub(crate) finished_at: u64,
+    #[serde(default)]
+    pub(crate) stages: Vec<StageTiming>,
+    pub(crate) outcome: Outcome,
+    #[serde(flatten)]
+    pub(crate) report: RequestReport,
+}
+
+// Every tty instance has its own history, which outlives the tty sessions (like the parked requests).
+pub(crate) fn history_file(instance: &str) -> Result<PathBuf, anyhow::Error> {
+    let history_dir = dirs::data_local_dir()
+        .ok_or_else(|| anyhow!("Couldn't find the local data directory!"))?
+        .join("yt-dlp-wrapper")
+        .join("history");
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&history_dir)
+        .map_err(|err| anyhow!("Failed to create '{}'!\n{err}", history_dir.display()))?;
+
+    Ok(history_dir.join(format!("{instance}.jsonl")))
+}
+
+pub(crate) fn append(path: &Path, entry: &HistoryEntry) -> Result<(), anyhow::Error> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    // a single write, so that a crash can't leave half a line behind
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+// oldest first, lines that can't be parsed are reported and skipped
+pub(crate) fn read(path: &Path) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => eprintln!(
+                "{} invalid history entry at line {}: {err}",
+                style("Skipping").for_stderr().yellow(),
+                index + 1
+            ),
+        }
+    }
+
+    Ok(entries)
+}
+
+impl cli::HistoryFilterArgs {
+    pub(crate) fn matches(&self, entry: &HistoryEntry) -> bool {
+        if self.since.is_some_and(|since| entry.finished_at < since)
+            || self.until.is_some_and(|until| entry.finished_at >= until)
+        {
+            return false;
+        }
+
+        if !self.outcome.is_empty() && !self.outcome.contains(&entry.outcome) {
+            return false;
+        }
+
+        match &self.artist {
+            None => true,
+            Some(artist) => {
+                let artist = artist.to_lowercase();
+                entry
+                    .report
+                    .recordings
+                    .iter()
+                    .any(|recording| recording.artist.to_lowercase().contains(&artist))
+            }
+        }
+    }
+}
+
+// the last attempt at every video request, for those that are worth another one (failed or aborted, unless the
+// filter says otherwise), oldest first
+fn retry_candidates<'a>(entries: &'a [HistoryEntry], filter: &cli::HistoryFilterArgs) -> Vec<&'a HistoryEntry> {
+    let mut seen = HashSet::new();
+    let mut candidates: Vec<&HistoryEntry> = entries
+        .iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.source_url.as_str()))
+        .filter(|entry| {
+            (!filter.outcome.is_empty() || matches!(entry.outcome, Outcome::Failed | Outcome::Aborted))
+                && filter.matches(entry)
+        })
+        .collect();
+    candidates.reverse();
+    candidates
+}
+
+// the original request (profile included), along with the recordings its files got tagged with
+fn retry_request(entry: &HistoryEntry, from_pid: u32) -> VideoRequest {
+    let mut request = entry.request.clone();
+    request.from_pid = from_pid;
+    for recording in &entry.report.recordings {
+        if let Some(file) = &recording.file {
+            request.chosen_recordings.insert(file.clone(), recording.id.clone());
+        }
+    }
+    request
+}
+
+// '--retry-failed', on tty startup
+pub(crate) fn enqueue_retries(vreq_queue: &VideoRequestQueue, path: &Path) {
+    let entries = match read(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!(
+                "{} '{}': {err}",
+                style("Failed to read the history file").for_stderr().red(),
+                path.display()
+            );
+            return;
+        }
+    };
+
+    let requests: Vec<VideoRequest> = retry_candidates(&entries, &cli::HistoryFilterArgs::default())
+        .into_iter()
+        .map(|entry| retry_request(entry, std::process::id()))
+        .collect();
+    if requests.is_empty() {
+        return;
+    }
+
+    let total = requests.len();
+    // the queue file might have brought some of them back already
+    let enqueued = vreq_queue
+        .try_push_batch(requests, false)
+        .iter()
+        .filter(|result| result.is_ok())
+        .count();
+    println!(
+        "{} {enqueued} of {total} failed or aborted video request(s) from the history",
+        style("Retrying").green()
+    );
+}
+
+pub(crate) async fn run(args: cli::HistoryArgs) -> Result<(), anyhow::Error> {
+    if let Some(cli::HistoryCommand::Retry(retry_args)) = args.command {
+        return retry(retry_args).await;
+    }
+
+    let path = history_file(&args.instance)?;
+    let entries: Vec<HistoryEntry> = read(&path)?
+        .into_iter()
+        .filter(|entry| args.filter.matches(entry))
+        .collect();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No video requests in '{}' match", path.display());
+        return Ok(());
+    }
+
+    for entry in &entries {
+        println!(
+            "{} (pid {}, profile: {})",
+            style(format_utc(entry.finished_at)).dim(),
+            entry.request.from_pid,
+            entry.request.profile.as_deref().unwrap_or("<tty's own>")
+        );
+        request::print_outcome(&entry.source_url, entry.id, entry.outcome, &entry.report);
+    }
+
+    Ok(())
+}
+
+async fn retry(args: cli::HistoryRetryArgs) -> Result<(), anyhow::Error> {
+    let path = history_file(&args.connection.instance)?;
+    let entries = read(&path)?;
+    let candidates = retry_candidates(&entries, &args.filter);
+    if candidates.is_empty() {
+        println!("No video requests to retry in '{}'", path.display());
+        return Ok(());
+    }
+
+    let client = TtyClient::connect(&args.connection).await?;
+
+    let urls: Vec<String> = candidates.iter().map(|entry| entry.source_url.clone()).collect();
+    let video_requests: Vec<VideoRequest> = candidates
+        .iter()
+        .map(|entry| retry_request(entry, std::process::id()))
+        .collect();
+
+    let (_, rejected) = request::send_batch(&client, &urls, &video_requests, args.priority).await?;
+    if rejected == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{rejected} of {} video request(s) couldn't be enqueued",
+            video_requests.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, video_id: &str, finished_at: u64, outcome: &str, recordings: &str) -> HistoryEntry {
+        serde_json::from_str(&format!(
+            r#"{{"id":{id},"source_url":"https://www.youtube.com/watch?v={video_id}","target":"{video_id}",
+                "from_pid":1,"profile":"music","enqueued_at":0,"finished_at":{finished_at},"outcome":"{outcome}",
+                "recordings":[{recordings}]}}"#
+        ))
+        .unwrap()
+    }
+
+    const RECORDING: &str = r#"{"id":"8f3471b5-7e6a-48da-86a9-c1c07a0f47ae","title":"Roygbiv","artist":"Boards of Canada","file":"roygbiv.opus"}"#;
+
+    fn ids(candidates: Vec<&HistoryEntry>) -> Vec<u64> {
+        candidates.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn retries_the_last_attempt_only() {
+        let entries = [
+            entry(1, "dQw4w9WgXcQ", 100, "failed", ""),
+            entry(2, "lalOy8Mbfdc", 200, "aborted", RECORDING),
+            entry(3, "yZv2daTWRZU", 300, "cancelled", ""),
+            entry(4, "0zM3nApSvMg", 400, "failed", ""),
+            // got imported on a later attempt
+            entry(5, "dQw4w9WgXcQ", 500, "imported", ""),
+            entry(6, "0zM3nApSvMg", 600, "aborted", ""),
+        ];
+
+        let filter = cli::HistoryFilterArgs::default();
+        assert_eq!(ids(retry_candidates(&entries, &filter)), [2, 6]);
+
+        let filter = cli::HistoryFilterArgs {
+            outcome: vec![Outcome::Cancelled, Outcome::Failed],
+            ..Default::default()
+        };
+        assert_eq!(ids(retry_candidates(&entries, &filter)), [3]);
+
+        let filter = cli::HistoryFilterArgs {
+            since: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(ids(retry_candidates(&entries, &filter)), [6]);
+
+        let filter = cli::HistoryFilterArgs {
+            artist: Some(String::from("boards OF")),
+            ..Default::default()
+        };
+        assert_eq!(ids(retry_candidates(&entries, &filter)), [2]);
+    }
+
+    #[test]
+    fn retries_keep_the_profile_and_the_chosen_recordings() {
+        let retried = entry(2, "lalOy8Mbfdc", 200, "aborted", RECORDING);
+        let request = retry_request(&retried, 42);
+
+        assert_eq!(request.target, "lalOy8Mbfdc");
+        assert_eq!(request.profile.as_deref(), Some("music"));
+        assert_eq!(request.from_pid, 42);
+        assert_eq!(
+            request.chosen_recordings.get("roygbiv.opus").map(String::as_str),
+            Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae")
+        );
+        assert!(request.validate(&[]).is_ok());
+
+        // and the ones chosen by earlier attempts, since the history keeps the retried requests as they were sent
+        let mut retried_again = entry(7, "lalOy8Mbfdc", 700, "failed", "");
+        retried_again.request = request;
+        assert_eq!(retry_request(&retried_again, 43).chosen_recordings.len(), 1);
+    }
+}
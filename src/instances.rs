@@ -0,0 +1,65 @@
+use crate::cli;
+use crate::lock;
+use crate::lock::LockfileContents;
+use crate::utils::time::{format_age, unix_now};
+use console::style;
+
+// Lists the tty instances that are running, found through their lockfiles.
+pub(crate) async fn run(args: cli::InstancesArgs) -> Result<(), anyhow::Error> {
+    let instances = tokio::task::spawn_blocking(running_instances).await??;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&instances)?);
+        return Ok(());
+    }
+
+    if instances.is_empty() {
+        println!("No tty instance is running");
+        return Ok(());
+    }
+
+    let now = unix_now();
+    for instance in &instances {
+        let endpoint = match &instance.endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => String::from("starting"),
+        };
+
+        println!(
+            "{} {endpoint} (pid {}, profile: {}, version {}, up {})",
+            style(&instance.instance).cyan(),
+            instance.pid,
+            instance.profile,
+            instance.version,
+            format_age(now.saturating_sub(instance.started_at))
+        );
+    }
+
+    Ok(())
+}
+
+fn running_instances() -> Result<Vec<LockfileContents>, anyhow::Error> {
+    let mut running = Vec::new();
+    for instance in lock::known_instances()? {
+        // leftover lockfiles of instances that exited
+        if !lock::is_tty_running(&instance)? {
+            continue;
+        }
+
+        match lock::read_contents(&instance) {
+            Ok(Some(contents)) => running.push(LockfileContents {
+                // it's only needed to talk to the instance
+                token: None,
+                ..contents
+            }),
+            // not written yet
+            Ok(None) => {}
+            Err(err) => eprintln!(
+                "{} instance '{instance}': {err}",
+                style("Skipping").for_stderr().yellow()
+            ),
+        }
+    }
+
+    Ok(running)
+}
@@ -1,10 +1,141 @@
+use crate::utils::time::unix_now;
 use anyhow::anyhow;
 use ouroboros::self_referencing;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{ErrorKind, Seek, Write};
-use std::path::Path;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
-static LOCKFILE_PATH_STR: &str = "/tmp/a81f7509-2019-4fb9-8d72-ba66c897df34.lock";
+// Bumped whenever request instances and the tty stop understanding each other (lockfile or http api changes).
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+// Where request instances can reach the tty.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TtyEndpoint {
+    Port(u16),
+    UnixSocket(PathBuf),
+}
+
+impl Display for TtyEndpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TtyEndpoint::Port(port) => write!(f, "127.0.0.1:{port}"),
+            TtyEndpoint::UnixSocket(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// Written by the tty as json, only readable by its owner as it holds the token.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct LockfileContents {
+    pub(crate) protocol: u32,
+    pub(crate) pid: u32,
+    // always there in the lockfile, the tty only writes it once its listener is bound
+    pub(crate) endpoint: Option<TtyEndpoint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+    // unix timestamp (seconds)
+    pub(crate) started_at: u64,
+    // yt-dlp-wrapper version of the tty
+    pub(crate) version: String,
+    pub(crate) profile: String,
+    #[serde(default = "default_instance")]
+    pub(crate) instance: String,
+}
+
+fn default_instance() -> String {
+    String::from(DEFAULT_INSTANCE)
+}
+
+impl LockfileContents {
+    pub(crate) fn new(profile: &str, instance: &str) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            pid: std::process::id(),
+            endpoint: None,
+            token: None,
+            started_at: unix_now(),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            profile: String::from(profile),
+            instance: String::from(instance),
+        }
+    }
+}
+
+// What request instances need to talk to the tty.
+#[derive(Debug, Clone)]
+pub(crate) struct TtyLockInfo {
+    pub(crate) endpoint: TtyEndpoint,
+    pub(crate) token: Option<String>,
+}
+
+// The runtime dir is private to the user (and cleaned up on logout), the cache dir is a per-user fallback.
+// Either way, two users on the same machine don't share the lockfile (nor the unix socket).
+pub(crate) fn runtime_dir() -> Result<PathBuf, anyhow::Error> {
+    let base_dir = dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .ok_or_else(|| anyhow!("Couldn't find either the runtime or the cache directory!"))?;
+    let runtime_dir = base_dir.join("yt-dlp-wrapper");
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&runtime_dir)
+        .map_err(|err| anyhow!("Failed to create '{}'!\n{err}", runtime_dir.display()))?;
+    // the mode above only applies when the directory gets created, an older one could be readable by others
+    std::fs::set_permissions(&runtime_dir, std::fs::Permissions::from_mode(0o700)).map_err(|err| {
+        anyhow!(
+            "Failed to restrict the permissions of '{}'!\n{err}",
+            runtime_dir.display()
+        )
+    })?;
+
+    Ok(runtime_dir)
+}
+
+// Every tty instance has its own lockfile (and socket), named after it.
+pub(crate) const DEFAULT_INSTANCE: &str = "default";
+
+fn instance_file_stem(instance: &str) -> String {
+    match instance {
+        DEFAULT_INSTANCE => String::from("tty"),
+        instance => format!("tty-{instance}"),
+    }
+}
+
+pub(crate) fn lockfile_path(instance: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(runtime_dir()?.join(format!("{}.lock", instance_file_stem(instance))))
+}
+
+pub(crate) fn default_socket_path(instance: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(runtime_dir()?.join(format!("{}.sock", instance_file_stem(instance))))
+}
+
+// the names of the instances whose lockfile exists, even if they aren't running anymore
+pub(crate) fn known_instances() -> Result<Vec<String>, anyhow::Error> {
+    let mut instances = Vec::new();
+    for dir_entry in std::fs::read_dir(runtime_dir()?)? {
+        let file_name = dir_entry?.file_name();
+        let Some(stem) = file_name.to_str().and_then(|name| name.strip_suffix(".lock")) else {
+            continue;
+        };
+
+        match stem {
+            "tty" => instances.push(String::from(DEFAULT_INSTANCE)),
+            stem => {
+                if let Some(instance) = stem.strip_prefix("tty-") {
+                    instances.push(String::from(instance));
+                }
+            }
+        }
+    }
+
+    instances.sort();
+    Ok(instances)
+}
 
 fn soft_open_rw_or_create_if_missing(path: &Path) -> Result<File, anyhow::Error> {
     let file = File::options().read(true).write(true).open(path);
@@ -18,6 +149,8 @@ fn soft_open_rw_or_create_if_missing(path: &Path) -> Result<File, anyhow::Error>
                     .write(true)
                     .create(true)
                     .truncate(false)
+                    // it holds the tty's token
+                    .mode(0o600)
                     .open(&path)?;
 
                 Ok(file)
@@ -27,78 +160,101 @@ fn soft_open_rw_or_create_if_missing(path: &Path) -> Result<File, anyhow::Error>
     }
 }
 
-pub(crate) fn get_lock() -> Result<fd_lock::RwLock<File>, anyhow::Error> {
-    let lockfile_path = Path::new(LOCKFILE_PATH_STR);
-    let lockfile = soft_open_rw_or_create_if_missing(lockfile_path)?;
+pub(crate) fn get_lock(instance: &str) -> Result<fd_lock::RwLock<File>, anyhow::Error> {
+    let lockfile = soft_open_rw_or_create_if_missing(&lockfile_path(instance)?)?;
     let lock = fd_lock::RwLock::new(lockfile);
     Ok(lock)
 }
 
-pub(crate) fn write_pid_port(guard: &mut fd_lock::RwLockWriteGuard<File>, port: u16) -> Result<(), std::io::Error> {
-    let pid = std::process::id();
+pub(crate) fn write_contents(
+    guard: &mut fd_lock::RwLockWriteGuard<File>,
+    contents: &LockfileContents,
+) -> Result<(), anyhow::Error> {
+    let contents = serde_json::to_string_pretty(contents)?;
     guard.set_len(0)?;
     guard.rewind()?;
-    let contents = format!("{pid}\n{port}");
     guard.write_all(contents.as_bytes())?;
     Ok(())
 }
 
-pub(crate) fn ensure_tty_running_and_read_port() -> Result<u16, anyhow::Error> {
-    {
-        let mut lock = get_lock()?;
-
-        match lock.try_write() {
-            Ok(_guard) => {
-                return Err(anyhow!("TTY instance isn't running!"));
-            }
-            Err(err) => match err.kind() {
-                ErrorKind::WouldBlock => {
-                    // tty instance is running
-                }
-                _ => return Err(anyhow!("Invalid error kind: {}; {}", err.kind(), err)),
-            },
-        };
-    } // drop lock, just in case
+pub(crate) fn clear_contents(guard: &mut fd_lock::RwLockWriteGuard<File>) -> Result<(), anyhow::Error> {
+    guard.set_len(0)?;
+    Ok(())
+}
 
-    fn get_pid_port() -> Result<(u32, u16), anyhow::Error> {
-        // bypass lock
-        let contents = std::fs::read_to_string(LOCKFILE_PATH_STR)?;
-        let mut contents = contents.split('\n');
+// bypasses the lock, the tty holds it. None if the tty didn't write anything yet
+pub(crate) fn read_contents(instance: &str) -> Result<Option<LockfileContents>, anyhow::Error> {
+    #[derive(serde::Deserialize)]
+    struct Versioned {
+        protocol: u32,
+        #[serde(default)]
+        version: Option<String>,
+    }
 
-        let pid = match contents.next().map(|pid| pid.parse::<u32>()) {
-            None => return Err(anyhow!("Invalid tty lockfile (couldn't find pid)!")),
-            Some(Err(err)) => return Err(anyhow!("Invalid pid in lockfile: {}", err)),
-            Some(Ok(pid)) => pid,
-        };
+    let lockfile_path = lockfile_path(instance)?;
+    let contents = std::fs::read_to_string(&lockfile_path)?;
+    if contents.is_empty() {
+        return Ok(None);
+    }
 
-        let port = match contents.next().map(|port| port.parse::<u16>()) {
-            None => return Err(anyhow!("Invalid tty lockfile (couldn't find port)!")),
-            Some(Err(err)) => return Err(anyhow!("Invalid port in lockfile: {}", err)),
-            Some(Ok(pid)) => pid,
-        };
+    // the protocol is checked first, as newer versions could change the rest of the lockfile
+    let versioned: Versioned = serde_json::from_str(&contents).map_err(|err| {
+        anyhow!(
+            "Invalid tty lockfile '{}', was it written by an incompatible yt-dlp-wrapper version?\n{err}",
+            lockfile_path.display()
+        )
+    })?;
+    if versioned.protocol != PROTOCOL_VERSION {
+        return Err(anyhow!(
+            "The tty instance (yt-dlp-wrapper {}) speaks protocol version {}, but this is yt-dlp-wrapper {} (protocol version {PROTOCOL_VERSION})! Restart the tty with the same version.",
+            versioned.version.as_deref().unwrap_or("<unknown version>"),
+            versioned.protocol,
+            env!("CARGO_PKG_VERSION"),
+        ));
+    }
 
-        if contents.next().is_some() {
-            eprintln!("WARNING: Malformed lockfile!");
-        }
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|err| anyhow!("Invalid tty lockfile '{}'!\n{err}", lockfile_path.display()))
+}
 
-        Ok((pid, port))
+pub(crate) fn is_tty_running(instance: &str) -> Result<bool, anyhow::Error> {
+    // don't leave lockfiles behind for instances that never existed
+    if !lockfile_path(instance)?.exists() {
+        return Ok(false);
     }
 
-    // retry 4 times
-    for _ in 0..4 {
-        let (_pid, port) = get_pid_port()?;
+    let mut lock = get_lock(instance)?;
 
-        if port == 0 {
-            // tty is initializing tcp listener, wait a bit
-            std::thread::sleep(std::time::Duration::from_millis(200));
-            continue;
-        } else {
-            return Ok(port);
-        }
+    match lock.try_write() {
+        Ok(_guard) => Ok(false),
+        Err(err) => match err.kind() {
+            // the tty instance holds the lock
+            ErrorKind::WouldBlock => Ok(true),
+            _ => Err(anyhow!("Invalid error kind: {}; {}", err.kind(), err)),
+        },
     }
+}
 
-    // tty probably crashed before initializing fully
-    Err(anyhow!("lockfile PORT is set to 0, did the tty instance crash?"))
+pub(crate) fn ensure_tty_running_and_read_info(instance: &str) -> Result<TtyLockInfo, anyhow::Error> {
+    if !is_tty_running(instance)? {
+        return Err(match instance {
+            DEFAULT_INSTANCE => anyhow!("TTY instance isn't running!"),
+            instance => anyhow!("TTY instance '{instance}' isn't running!"),
+        });
+    }
+
+    match read_contents(instance)? {
+        Some(LockfileContents {
+            endpoint: Some(endpoint),
+            token,
+            ..
+        }) => Ok(TtyLockInfo { endpoint, token }),
+        // the tty writes the lockfile right after binding its listener
+        _ => Err(anyhow!(
+            "The lockfile has no port (nor socket) yet, is the tty instance still starting? Try again in a moment"
+        )),
+    }
 }
 
 #[self_referencing]
@@ -110,14 +266,15 @@ pub(crate) struct InstanceLock {
 }
 
 impl InstanceLock {
-    pub(crate) fn lock_or_panic() -> Self {
-        let lock = get_lock().expect("Failed to create lock for lockfile");
+    pub(crate) fn lock_or_panic(instance: &str) -> Self {
+        let lock = get_lock(instance).expect("Failed to create lock for lockfile");
 
         InstanceLockBuilder {
             lock,
             guard_builder: |lock: &mut fd_lock::RwLock<File>| {
-                lock.try_write()
-                    .expect("Failed to acquire lock guard, is another tty instance already running?")
+                lock.try_write().unwrap_or_else(|err| {
+                    panic!("Failed to acquire lock guard, is another tty instance named '{instance}' already running? ({err})")
+                })
             },
         }
         .build()
@@ -1,33 +1,71 @@
+pub(crate) mod api;
+pub(crate) mod auth;
 pub(crate) mod cli;
+pub(crate) mod client;
+pub(crate) mod config;
+pub(crate) mod control;
+pub(crate) mod events;
 pub(crate) mod fingerprinting;
+pub(crate) mod history;
+pub(crate) mod instances;
 pub(crate) mod lock;
 pub(crate) mod musicbrainz;
 pub(crate) mod net;
+pub(crate) mod prepare;
 pub(crate) mod process;
+pub(crate) mod queue;
+pub(crate) mod queue_view;
 pub(crate) mod request;
+pub(crate) mod review;
 pub(crate) mod signals;
 pub(crate) mod tty;
 pub(crate) mod user;
 pub(crate) mod video;
+pub(crate) mod youtube;
 
 pub(crate) mod utils;
 
-use cli::{CliArgs, Command, Parser};
+use clap::{CommandFactory, FromArgMatches};
+use cli::{CliArgs, Command};
+use std::process::ExitCode;
 use std::sync::Arc;
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    let args = CliArgs::parse();
+fn main() -> Result<ExitCode, anyhow::Error> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
+    // a prompt interrupted by 'stop --now' keeps its blocking thread waiting for input, don't wait for it too
+    runtime.shutdown_timeout(std::time::Duration::from_secs(1));
+
+    result
+}
+
+async fn run() -> Result<ExitCode, anyhow::Error> {
+    let matches = CliArgs::command().get_matches();
+    let args = CliArgs::from_arg_matches(&matches)?;
+    // the config file can only fill in what wasn't explicitly given
+    let explicit_args = matches
+        .subcommand()
+        .map(|(_, matches)| config::explicit_args(matches))
+        .unwrap_or_default();
+
     match args.command {
-        Command::Tty(args) => {
-            args.yt_dlp_display.get_or_init(|| args.yt_dlp.display().to_string());
-            args.beet_display.get_or_init(|| args.beet.display().to_string());
-            args.fpcalc_display.get_or_init(|| args.fpcalc.display().to_string());
-            args.ffmpeg_display.get_or_init(|| args.ffmpeg.display().to_string());
+        Command::Tty(mut args) => {
+            args.explicit_args = explicit_args;
+            let profiles = config::resolve_profiles(args)?;
 
-            tty::run(Arc::new(args)).await;
-            Ok(())
+            tty::run(Arc::new(profiles)).await;
+            Ok(ExitCode::SUCCESS)
         }
         Command::Request(args) => request::run(args).await,
+        Command::Queue(args) => queue_view::run(args).await.map(|()| ExitCode::SUCCESS),
+        Command::Instances(args) => instances::run(args).await.map(|()| ExitCode::SUCCESS),
+        Command::Status(args) => control::run_status(args).await,
+        Command::Stop(args) => control::run_stop(args).await,
+        Command::Review(args) => review::run(args).await.map(|()| ExitCode::SUCCESS),
+        Command::History(args) => history::run(args).await.map(|()| ExitCode::SUCCESS),
+        Command::Config(mut args) => {
+            args.explicit_args = explicit_args;
+            config::run(args).map(|()| ExitCode::SUCCESS)
+        }
     }
 }
@@ -1,3 +1,4 @@
+use crate::user;
 use console::style;
 use musicbrainz_rs::Fetch;
 use std::collections::HashMap;
@@ -14,6 +15,15 @@ pub(crate) async fn fetch_recording_data(
         .map(Arc::new)
 }
 
+// MusicBrainz ids are lowercase hyphenated uuids
+pub(crate) fn is_valid_mbid(mbid: &str) -> bool {
+    mbid.len() == 36
+        && mbid.char_indices().all(|(index, c)| match index {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_digit() || ('a'..='f').contains(&c),
+        })
+}
+
 pub(crate) async fn fetch_all_recordings_with_interact<A, S>(
     mbids: A,
 ) -> Vec<Arc<musicbrainz_rs::entity::recording::Recording>>
@@ -55,19 +65,28 @@ where
         if cache.values().all(Option::is_some) {
             break 'interact;
         } else {
-            let retry = dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt(format!(
-                    "{} {}, retry?",
-                    style(cache.values().filter(|opt| opt.is_none()).count()).red(),
-                    style("MusicBrainz API calls have failed").red(),
-                ))
-                .default(true)
-                .show_default(true)
-                .wait_for_newline(true)
-                .interact();
+            let prompt = format!(
+                "{} {}, retry?",
+                style(cache.values().filter(|opt| opt.is_none()).count()).red(),
+                style("MusicBrainz API calls have failed").red(),
+            );
+            let retry = match user::headless_retry("Retry the failed MusicBrainz API calls?") {
+                Some(retry) => Ok(Ok(retry)),
+                None => {
+                    user::interact("Retry the failed MusicBrainz API calls?", move || {
+                        dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                            .with_prompt(prompt)
+                            .default(true)
+                            .show_default(true)
+                            .wait_for_newline(true)
+                            .interact()
+                    })
+                    .await
+                }
+            };
 
             match retry {
-                Ok(true) => continue 'interact,
+                Ok(Ok(true)) => continue 'interact,
                 _ => break 'interact,
             }
         }
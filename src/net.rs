@@ -1,11 +1,25 @@
+use crate::api;
+use crate::auth;
+use crate::auth::AuthState;
+use crate::config::Profiles;
+use crate::control;
+use crate::events;
+use crate::lock::LockfileContents;
+use crate::queue::{QueueEditError, QueueInfo, QueuePushError, RequestStatus, VideoRequestQueue};
+use crate::review;
 use crate::video::VideoRequest;
+use axum::http::{header, HeaderValue, Method};
 use axum::response::Response;
-use axum::routing::post;
-use tokio::sync::mpsc::error::TrySendError;
+use axum::routing::{get, post};
+use console::style;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub(crate) struct TtyAxumState {
-    pub(crate) vreq_sender: tokio::sync::mpsc::Sender<VideoRequest>,
+    pub(crate) queue: Arc<VideoRequestQueue>,
+    pub(crate) profiles: Arc<Profiles>,
+    // the lockfile's contents, without the token
+    pub(crate) tty_info: Arc<LockfileContents>,
 }
 
 pub(crate) struct HttpMsgResponse {
@@ -25,6 +39,68 @@ impl axum::response::IntoResponse for HttpMsgResponse {
     }
 }
 
+// what happened to each request of a batch, in the same order they were sent
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum BatchItemResult {
+    Accepted {
+        id: u64,
+        // when the same video was already imported, see VideoRequestQueue::imported_at
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        imported_at: Option<u64>,
+    },
+    QueueFull,
+    Duplicate,
+    Rejected {
+        reason: String,
+    },
+}
+
+pub(crate) mod get {
+    use super::*;
+
+    pub(crate) async fn queue(
+        axum::extract::State(state): axum::extract::State<TtyAxumState>,
+    ) -> axum::Json<QueueInfo> {
+        axum::Json(state.queue.info(&state.profiles.selected().profile))
+    }
+
+    pub(crate) async fn request_status(
+        axum::extract::State(state): axum::extract::State<TtyAxumState>,
+        axum::extract::Path(id): axum::extract::Path<u64>,
+    ) -> Result<axum::Json<RequestStatus>, HttpMsgResponse> {
+        state.queue.status(id).map(axum::Json).ok_or_else(|| {
+            HttpMsgResponse::new(
+                axum::http::StatusCode::NOT_FOUND,
+                format!("Unknown video request id {id}!"),
+            )
+        })
+    }
+}
+
+// Server-sent events, one json object per event (see events::Event), with the event's name as the sse event type.
+pub(crate) async fn events_stream() -> axum::response::sse::Sse<
+    impl tokio_stream::Stream<Item = Result<axum::response::sse::Event, std::convert::Infallible>>,
+> {
+    use axum::response::sse;
+    use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+    use tokio_stream::StreamExt;
+
+    let stream = tokio_stream::wrappers::BroadcastStream::new(events::subscribe()).map(|event| {
+        let sse_event = match event {
+            Ok(event) => sse::Event::default().event(event.name()).json_data(&event),
+            // slow subscribers just get told how much they missed
+            Err(BroadcastStreamRecvError::Lagged(missed)) => sse::Event::default()
+                .event("lagged")
+                .json_data(serde_json::json!({ "event": "lagged", "missed": missed })),
+        };
+
+        Ok(sse_event.unwrap_or_else(|err| sse::Event::default().event("error").data(err.to_string())))
+    });
+
+    sse::Sse::new(stream).keep_alive(sse::KeepAlive::default())
+}
+
 pub(crate) mod post {
     use super::*;
 
@@ -32,28 +108,195 @@ pub(crate) mod post {
         axum::extract::State(state): axum::extract::State<TtyAxumState>,
         axum::Form(vreq): axum::Form<VideoRequest>,
     ) -> Result<(), HttpMsgResponse> {
-        match state.vreq_sender.try_send(vreq) {
+        let Some(args) = state.profiles.get(vreq.profile.as_deref()) else {
+            return Err(HttpMsgResponse::new(
+                axum::http::StatusCode::BAD_REQUEST,
+                format!(
+                    "Cannot enqueue: Unknown profile '{}'!",
+                    vreq.profile.unwrap_or_default()
+                ),
+            ));
+        };
+
+        if let Err(err) = vreq.validate(&args.allowed_hosts) {
+            return Err(HttpMsgResponse::new(
+                axum::http::StatusCode::FORBIDDEN,
+                format!("Cannot enqueue: {err}!"),
+            ));
+        }
+
+        match state.queue.try_push(vreq) {
             Ok(_) => Ok(()),
             Err(error) => match error {
-                TrySendError::Full(_) => Err(HttpMsgResponse::new(
+                QueuePushError::Full(_) => Err(HttpMsgResponse::new(
                     axum::http::StatusCode::SERVICE_UNAVAILABLE,
                     String::from("Cannot enqueue: Video request queue capacity exceeded!"),
                 )),
-                // shouldn't happen
-                TrySendError::Closed(_) => Err(HttpMsgResponse::new(
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    String::from("Cannot enqueue: Video request queue closed!"),
+                QueuePushError::Duplicate(_) => Err(HttpMsgResponse::new(
+                    axum::http::StatusCode::CONFLICT,
+                    String::from("Cannot enqueue: Video request is already in the queue!"),
                 )),
+                QueuePushError::Stopping(_) => Err(stopping()),
             },
         }
     }
+
+    #[derive(serde::Deserialize)]
+    pub(crate) struct BatchParams {
+        #[serde(default)]
+        priority: bool,
+    }
+
+    pub(crate) async fn video_requests(
+        axum::extract::State(state): axum::extract::State<TtyAxumState>,
+        axum::extract::Query(params): axum::extract::Query<BatchParams>,
+        axum::Json(vreqs): axum::Json<Vec<VideoRequest>>,
+    ) -> Result<axum::Json<Vec<BatchItemResult>>, HttpMsgResponse> {
+        let mut results = Vec::with_capacity(vreqs.len());
+        let mut valid = Vec::with_capacity(vreqs.len());
+        let mut imported_at = Vec::with_capacity(vreqs.len());
+
+        for vreq in vreqs {
+            let validation = match state.profiles.get(vreq.profile.as_deref()) {
+                Some(args) => vreq.validate(&args.allowed_hosts),
+                None => Err(format!(
+                    "Unknown profile '{}'",
+                    vreq.profile.as_deref().unwrap_or_default()
+                )),
+            };
+
+            match validation {
+                Ok(()) => {
+                    // placeholder, replaced below once the valid requests went through the queue
+                    results.push(None);
+                    imported_at.push(state.queue.imported_at(&vreq));
+                    valid.push(vreq);
+                }
+                Err(reason) => results.push(Some(BatchItemResult::Rejected { reason })),
+            }
+        }
+
+        let pushed = state.queue.try_push_batch(valid, params.priority);
+        // the whole batch gets refused, the requests can be sent again to the next tty session
+        if pushed
+            .iter()
+            .any(|result| matches!(result, Err(QueuePushError::Stopping(_))))
+        {
+            return Err(stopping());
+        }
+
+        let mut pushed = pushed.into_iter().zip(imported_at);
+        let results = results
+            .into_iter()
+            .map(|result| match result {
+                Some(result) => result,
+                None => match pushed.next() {
+                    Some((Ok(id), imported_at)) => BatchItemResult::Accepted { id, imported_at },
+                    Some((Err(QueuePushError::Full(_)), _)) => BatchItemResult::QueueFull,
+                    Some((Err(QueuePushError::Duplicate(_)), _)) => BatchItemResult::Duplicate,
+                    // handled above, try_push_batch returns one result per request
+                    Some((Err(QueuePushError::Stopping(_)), _)) | None => unreachable!(),
+                },
+            })
+            .collect();
+
+        Ok(axum::Json(results))
+    }
+
+    fn stopping() -> HttpMsgResponse {
+        HttpMsgResponse::new(
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            String::from("Cannot enqueue: The tty is stopping!"),
+        )
+    }
+}
+
+pub(crate) mod queue {
+    use super::*;
+
+    fn edit_error(err: QueueEditError) -> HttpMsgResponse {
+        let status_code = match err {
+            QueueEditError::NotFound(_) => axum::http::StatusCode::NOT_FOUND,
+            QueueEditError::Current(_) => axum::http::StatusCode::CONFLICT,
+        };
+
+        HttpMsgResponse::new(status_code, format!("{err}!"))
+    }
+
+    pub(crate) async fn delete(
+        axum::extract::State(state): axum::extract::State<TtyAxumState>,
+        axum::extract::Path(id): axum::extract::Path<u64>,
+    ) -> Result<(), HttpMsgResponse> {
+        let vreq = state.queue.remove(id).map_err(edit_error)?;
+        println!(
+            "{} video request {id} ({}) from the queue",
+            style("Cancelled").yellow(),
+            vreq.source_url()
+        );
+
+        Ok(())
+    }
+
+    pub(crate) async fn move_to_front(
+        axum::extract::State(state): axum::extract::State<TtyAxumState>,
+        axum::extract::Path(id): axum::extract::Path<u64>,
+    ) -> Result<(), HttpMsgResponse> {
+        state.queue.move_to_front(id).map_err(edit_error)
+    }
 }
 
-pub(crate) async fn start_axum_app(vreq_sender: tokio::sync::mpsc::Sender<VideoRequest>, tcpl: std::net::TcpListener) {
+pub(crate) enum Listener {
+    Tcp(std::net::TcpListener),
+    Unix(std::os::unix::net::UnixListener),
+}
+
+pub(crate) async fn start_axum_app(
+    queue: Arc<VideoRequestQueue>,
+    profiles: Arc<Profiles>,
+    tty_info: Arc<LockfileContents>,
+    listener: Listener,
+    auth: AuthState,
+    cors_origins: &[String],
+) {
+    let auth_layer = axum::middleware::from_fn_with_state(auth, auth::check_request);
+
+    // the cors layer answers the browsers' preflight requests, which can't carry the token
+    let cors_origins = cors_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Invalid cors origin");
+    let cors_layer = tower_http::cors::CorsLayer::new()
+        .allow_origin(cors_origins)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
+
     let app = axum::Router::new()
         .route("/video-request", post(post::video_request))
-        .with_state(TtyAxumState { vreq_sender });
+        .route("/video-requests", post(post::video_requests))
+        .route("/queue", get(get::queue))
+        .route("/events", get(events_stream))
+        .route("/status", get(control::status))
+        .route("/stop", post(control::stop))
+        .route("/review", get(review::list_parked).post(review::start_review))
+        .route("/queue/{id}", get(get::request_status).delete(queue::delete))
+        .route("/queue/{id}/front", post(queue::move_to_front))
+        .layer(auth_layer.clone())
+        .nest(api::PREFIX, api::router().layer(auth_layer).layer(cors_layer))
+        .with_state(TtyAxumState {
+            queue,
+            profiles,
+            tty_info,
+        });
 
-    let tcpl = tokio::net::TcpListener::from_std(tcpl).unwrap();
-    axum::serve(tcpl, app).await.unwrap();
+    match listener {
+        Listener::Tcp(tcpl) => {
+            let tcpl = tokio::net::TcpListener::from_std(tcpl).unwrap();
+            axum::serve(tcpl, app).await.unwrap();
+        }
+        Listener::Unix(unixl) => {
+            let unixl = tokio::net::UnixListener::from_std(unixl).unwrap();
+            axum::serve(unixl, app).await.unwrap();
+        }
+    }
 }
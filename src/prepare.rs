@@ -0,0 +1,276 @@
+use crate::cli;
+use crate::config::Profiles;
+use crate::fingerprinting::acoustid;
+use crate::fingerprinting::file::{self, FPCalcJsonOutput, LookedUpFile};
+use crate::queue::{QueueEntry, VideoRequestQueue};
+use crate::video::{self, VideoRequestKind};
+use console::style;
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+
+// Background workers ('--workers') download (and fingerprint) the upcoming video requests while the current one
+// waits for answers. They never print nor ask anything: what their commands output is kept until the handler gets
+// to the request, and whatever fails is left for the handler to do again, interactively.
+pub(crate) struct Preparer {
+    // by queue entry id, aborting a preparation kills its command and removes its temp directory
+    preparations: Mutex<HashMap<u64, JoinHandle<Preparation>>>,
+}
+
+// a command that ran in the background, stdout and stderr interleaved (unless stdout was what we were after)
+struct CapturedCommand {
+    command: Vec<String>,
+    exit_code: Option<i32>,
+    output: Vec<u8>,
+}
+
+struct Preparation {
+    commands: Vec<CapturedCommand>,
+    // none if the download failed
+    prepared: Option<Prepared>,
+}
+
+pub(crate) struct Prepared {
+    pub(crate) work_dir: tempfile::TempDir,
+    // by filename, the files that got fingerprinted and looked up
+    pub(crate) files: HashMap<String, LookedUpFile>,
+}
+
+impl Preparer {
+    // prepares the first `workers` pending requests, following the queue as it changes
+    pub(crate) fn spawn(
+        workers: usize,
+        vreq_queue: Arc<VideoRequestQueue>,
+        profiles: Arc<Profiles>,
+        acoustid_client: reqwest::Client,
+    ) -> Arc<Self> {
+        let preparer = Arc::new(Preparer {
+            preparations: Mutex::new(HashMap::new()),
+        });
+
+        let scheduler = preparer.clone();
+        tokio::spawn(async move {
+            let mut changes = vreq_queue.subscribe_changes();
+            loop {
+                scheduler.schedule(workers, &vreq_queue, &profiles, &acoustid_client);
+                if changes.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        preparer
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, JoinHandle<Preparation>>> {
+        self.preparations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn schedule(
+        &self,
+        workers: usize,
+        vreq_queue: &VideoRequestQueue,
+        profiles: &Profiles,
+        acoustid_client: &reqwest::Client,
+    ) {
+        // looking at the queue while holding the lock means that the handler can't take a request in between,
+        // and miss the preparation that's about to start for it
+        let mut preparations = self.lock();
+        let (current, upcoming) = vreq_queue.upcoming(workers);
+
+        // cancelled (or pushed back) requests free their worker
+        preparations.retain(|id, preparation| {
+            let keep = current == Some(*id) || upcoming.iter().any(|entry| entry.id == *id);
+            if !keep {
+                preparation.abort();
+            }
+            keep
+        });
+
+        for entry in upcoming {
+            if preparations.contains_key(&entry.id) {
+                continue;
+            }
+
+            // playlists get expanded interactively, invalid requests are reported by the handler
+            let Some(args) = profiles.get(entry.request.profile.as_deref()) else {
+                continue;
+            };
+            if entry.request.kind == VideoRequestKind::Playlist || entry.request.validate(&args.allowed_hosts).is_err()
+            {
+                continue;
+            }
+
+            preparations.insert(
+                entry.id,
+                tokio::spawn(prepare(entry, args.clone(), acoustid_client.clone())),
+            );
+        }
+    }
+
+    // waits for the request's preparation (if it has one) and shows what its commands printed
+    pub(crate) async fn take(&self, id: u64) -> Option<Prepared> {
+        let preparation = self.lock().remove(&id)?;
+        if !preparation.is_finished() {
+            println!("{}", style("Waiting for the background download...").magenta());
+        }
+
+        let preparation = match preparation.await {
+            Ok(preparation) => preparation,
+            Err(err) => {
+                eprintln!("{}\n{err}", style("Background download failed!").for_stderr().red());
+                return None;
+            }
+        };
+
+        for command in &preparation.commands {
+            command.print();
+        }
+
+        if preparation.prepared.is_none() {
+            println!("{}", style("The background download failed, starting over").yellow());
+        }
+
+        preparation.prepared
+    }
+}
+
+impl CapturedCommand {
+    fn print(&self) {
+        println!();
+        println!(
+            "{}",
+            style(format!("Ran in the background: ['{}']", self.command.join("', '"))).cyan()
+        );
+        if let Err(err) = std::io::stdout().write_all(&self.output) {
+            eprintln!("Failed to print to stdout: {}", err);
+        }
+
+        match self.exit_code {
+            Some(0) => println!("{}", style("Command returned exit code 0.").green()),
+            Some(code) => println!("{}", style(format!("Command returned exit code {code}.")).red()),
+            None => println!("{}", style("Command didn't return an exit code.").red()),
+        }
+        println!();
+    }
+}
+
+async fn prepare(entry: QueueEntry, args: Arc<cli::TtyArgs>, mut acoustid_client: reqwest::Client) -> Preparation {
+    let mut preparation = Preparation {
+        commands: Vec::new(),
+        prepared: None,
+    };
+
+    let Ok(work_dir) = tempfile::tempdir() else {
+        return preparation;
+    };
+
+    let ytdlp_cmd = video::yt_dlp_command(&args, &entry.request);
+    if run_captured(&ytdlp_cmd, work_dir.path(), false, &mut preparation.commands)
+        .await
+        .is_none()
+    {
+        return preparation;
+    }
+
+    let mut files = HashMap::new();
+    if !args.skip_fingerprinting {
+        for filename in file::get_fingerprintable_filenames_in_directory(work_dir.path()) {
+            let filepath = work_dir.path().join(&filename);
+            if let Some(looked_up) = look_up(&filepath, &args, &mut acoustid_client, &mut preparation.commands).await {
+                files.insert(filename, looked_up);
+            }
+        }
+    }
+
+    preparation.prepared = Some(Prepared { work_dir, files });
+    preparation
+}
+
+// anything that fails here gets done again by the handler, which knows how to ask about it
+async fn look_up(
+    filepath: &Path,
+    args: &cli::TtyArgs,
+    acoustid_client: &mut reqwest::Client,
+    commands: &mut Vec<CapturedCommand>,
+) -> Option<LookedUpFile> {
+    let filepath_display = filepath.display().to_string();
+    let fpcalc_cmd = [args.fpcalc_display.get().unwrap().as_str(), "-json", &filepath_display];
+    let stdout = run_captured(&fpcalc_cmd, filepath.parent()?, true, commands).await?;
+    let fpcalc_output: FPCalcJsonOutput = serde_json::from_slice(&stdout).ok()?;
+
+    let lookup = acoustid::lookup_fingerprint(
+        acoustid_client,
+        &fpcalc_output.fingerprint,
+        fpcalc_output.duration.floor() as u64,
+        acoustid::ACOUSTID_CLIENT_KEY,
+    )
+    .await
+    .ok()?;
+    if lookup.status != "ok" {
+        return None;
+    }
+
+    let results = lookup.results.unwrap_or_default();
+    let (looked_up, _ignored) = LookedUpFile::new(filepath, fpcalc_output, results, args).await;
+    Some(looked_up)
+}
+
+// returns the command's stdout if it succeeded (only captured separately if `want_stdout`)
+async fn run_captured(
+    full_command: &[&str],
+    work_dir: &Path,
+    want_stdout: bool,
+    commands: &mut Vec<CapturedCommand>,
+) -> Option<Vec<u8>> {
+    // both streams go to the same file, so that they stay in order
+    let mut log = tempfile::tempfile().ok()?;
+
+    let mut command = tokio::process::Command::new(full_command[0]);
+    command
+        .args(&full_command[1..])
+        .current_dir(work_dir)
+        .stdin(Stdio::null())
+        .stderr(log.try_clone().ok()?)
+        // aborting the preparation drops the command
+        .kill_on_drop(true)
+        // CTRL-C in the terminal is meant for the current request
+        .process_group(0);
+    if want_stdout {
+        command.stdout(Stdio::piped());
+    } else {
+        command.stdout(log.try_clone().ok()?);
+    }
+
+    // unlike output(), keeps the redirections
+    let result = match command.spawn() {
+        Ok(child) => child.wait_with_output().await,
+        Err(err) => Err(err),
+    };
+
+    let mut output = Vec::new();
+    if log.rewind().is_ok() {
+        let _ = log.read_to_end(&mut output);
+    }
+
+    let (exit_code, stdout) = match result {
+        Ok(result) => (result.status.code(), result.status.success().then_some(result.stdout)),
+        Err(err) => {
+            output.extend_from_slice(format!("Failed to run the command: {err}\n").as_bytes());
+            (None, None)
+        }
+    };
+
+    commands.push(CapturedCommand {
+        command: full_command.iter().map(ToString::to_string).collect(),
+        exit_code,
+        output,
+    });
+
+    stdout
+}
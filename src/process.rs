@@ -1,3 +1,4 @@
+use crate::events::{self, Event};
 use crate::handle_ctrlc;
 use crate::user::{ask_what_to_do, WhatToDo};
 use console::{style, StyledObject};
@@ -80,11 +81,23 @@ where
     let mut command = tokio::process::Command::new(full_command[0]);
     command.args(&full_command[1..]);
     command.current_dir(work_dir);
+    // dropping the request (see 'stop --now') kills the command
+    command.kill_on_drop(true);
     user_settings(&mut command);
 
+    let command_display: Vec<String> = full_command.iter().map(ToString::to_string).collect();
+    events::emit(Event::CommandStarted {
+        command: command_display.clone(),
+    });
+
     let child = command.spawn()?;
     let (exit_status, result) = extract(child).await?;
 
+    events::emit(Event::CommandFinished {
+        command: command_display,
+        exit_code: exit_status.code(),
+    });
+
     before_context_return(&result);
 
     let return_to_tty = move |message: &StyledObject<String>| {
@@ -0,0 +1,1015 @@
+use crate::events::{self, Event};
+use crate::history;
+use crate::utils::time::unix_now;
+use crate::video::{Outcome, RequestReport, VideoRequest};
+use console::style;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum QueuePushError {
+    #[error("Video request queue capacity exceeded")]
+    Full(VideoRequest),
+    #[error("Video request is already in the queue")]
+    Duplicate(VideoRequest),
+    // nothing gets handled anymore, and the queue file was already written one last time
+    #[error("The tty is stopping")]
+    Stopping(VideoRequest),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum QueueEditError {
+    #[error("No pending video request with id {0}")]
+    NotFound(u64),
+    #[error("Video request {0} is already being processed, it can only be aborted from the tty")]
+    Current(u64),
+}
+
+// what the request currently being handled is waiting on
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Stage {
+    Starting,
+    ExpandingPlaylist,
+    Downloading,
+    Fingerprinting,
+    // AcoustID lookup and recording selection
+    Lookup,
+    Tagging,
+    Importing,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Starting => write!(f, "starting"),
+            Stage::ExpandingPlaylist => write!(f, "expanding playlist"),
+            Stage::Downloading => write!(f, "downloading"),
+            Stage::Fingerprinting => write!(f, "fingerprinting"),
+            Stage::Lookup => write!(f, "looking up"),
+            Stage::Tagging => write!(f, "tagging"),
+            Stage::Importing => write!(f, "importing"),
+        }
+    }
+}
+
+// when the request currently being handled entered (and left) one of its stages
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct StageTiming {
+    pub(crate) stage: Stage,
+    // unix timestamps (seconds)
+    pub(crate) started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) finished_at: Option<u64>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub(crate) struct QueueEntry {
+    pub(crate) id: u64,
+    // unix timestamp (seconds)
+    pub(crate) enqueued_at: u64,
+    #[serde(flatten)]
+    pub(crate) request: VideoRequest,
+}
+
+// hand-written queue file entries don't need an id or an enqueue time, they get assigned when loading them
+#[derive(serde::Deserialize, Debug)]
+struct QueueFileEntry {
+    id: Option<u64>,
+    enqueued_at: Option<u64>,
+    #[serde(flatten)]
+    request: VideoRequest,
+}
+
+#[derive(Debug)]
+struct CurrentEntry {
+    entry: QueueEntry,
+    stage: Stage,
+    started_at: u64,
+    // every stage it went through so far, in order (restarts go through them again)
+    stages: Vec<StageTiming>,
+}
+
+// what `GET /queue` answers with
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct QueueInfo {
+    pub(crate) current: Option<CurrentEntryInfo>,
+    pub(crate) pending: Vec<QueueEntryInfo>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct CurrentEntryInfo {
+    #[serde(flatten)]
+    pub(crate) entry: QueueEntryInfo,
+    pub(crate) stage: Stage,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct QueueEntryInfo {
+    pub(crate) id: u64,
+    pub(crate) source_url: String,
+    pub(crate) from_pid: u32,
+    pub(crate) enqueued_at: u64,
+    pub(crate) profile: String,
+}
+
+impl QueueEntryInfo {
+    fn new(entry: &QueueEntry, default_profile: &str) -> Self {
+        Self {
+            id: entry.id,
+            source_url: entry.request.source_url(),
+            from_pid: entry.request.from_pid,
+            enqueued_at: entry.enqueued_at,
+            profile: entry.request.profile.as_deref().unwrap_or(default_profile).to_string(),
+        }
+    }
+}
+
+// what `GET /queue/{id}` answers with
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum RequestStatus {
+    Pending { position: usize },
+    Processing { stage: Stage },
+    Finished { outcome: Outcome, report: RequestReport },
+}
+
+// request instances waiting for an outcome poll for it, so finished requests are kept around for a while
+const MAX_FINISHED: usize = 256;
+
+// the history (read on startup) is the record of every import, the duplicate warnings only need the recent ones
+const MAX_IMPORTED: usize = 8192;
+
+#[derive(Debug, Default)]
+struct QueueState {
+    // the request currently being handled, kept in the queue file until it's done
+    current: Option<CurrentEntry>,
+    pending: VecDeque<QueueEntry>,
+    // the most recently finished (or cancelled) requests, oldest first
+    finished: VecDeque<(u64, Outcome, RequestReport)>,
+    // ids are only unique within a tty session (and the queue file it restores)
+    last_id: u64,
+    // what we last wrote to (or read from) the queue file, used to tell our own writes apart from external edits
+    last_synced: Option<String>,
+    // set by a graceful stop, no other request gets handled
+    stopping: bool,
+    // a review session was asked for, it starts before the next request
+    review_requested: bool,
+    // when each source url got imported (by this tty session or the ones in the history), so that enqueueing it
+    // again can be warned about
+    imported: HashMap<String, u64>,
+}
+
+impl QueueState {
+    // for requests that are enqueued through the tty (not loaded from the queue file)
+    fn new_entry(&mut self, request: VideoRequest) -> QueueEntry {
+        self.last_id += 1;
+        events::emit(Event::Enqueued {
+            id: self.last_id,
+            source_url: request.source_url(),
+            profile: request.profile.clone(),
+        });
+
+        QueueEntry {
+            id: self.last_id,
+            enqueued_at: unix_now(),
+            request,
+        }
+    }
+
+    // keeps the ids of the queue file entries, unless they're missing or already taken
+    fn adopt_entries(&mut self, entries: Vec<QueueFileEntry>) -> VecDeque<QueueEntry> {
+        let mut taken: HashSet<u64> = self.current.iter().map(|current| current.entry.id).collect();
+        self.last_id = self
+            .last_id
+            .max(entries.iter().filter_map(|entry| entry.id).max().unwrap_or(0));
+
+        entries
+            .into_iter()
+            .map(|file_entry| {
+                let id = match file_entry.id {
+                    Some(id) if taken.insert(id) => id,
+                    _ => {
+                        self.last_id += 1;
+                        taken.insert(self.last_id);
+                        self.last_id
+                    }
+                };
+
+                QueueEntry {
+                    id,
+                    enqueued_at: file_entry.enqueued_at.unwrap_or_else(unix_now),
+                    request: file_entry.request,
+                }
+            })
+            .collect()
+    }
+
+    fn record_finished(&mut self, id: u64, outcome: Outcome, report: RequestReport) {
+        events::emit(Event::RequestFinished {
+            id,
+            outcome,
+            report: report.clone(),
+        });
+
+        if self.finished.len() >= MAX_FINISHED {
+            self.finished.pop_front();
+        }
+        self.finished.push_back((id, outcome, report));
+    }
+
+    // once full, the oldest half gets forgotten at once, which keeps the pruning cheap
+    fn record_imported(&mut self, source_url: String, imported_at: u64) {
+        self.imported.insert(source_url, imported_at);
+        if self.imported.len() <= MAX_IMPORTED {
+            return;
+        }
+
+        let mut imported_ats: Vec<u64> = self.imported.values().copied().collect();
+        let (_, &mut cutoff, _) = imported_ats.select_nth_unstable(MAX_IMPORTED / 2);
+        self.imported.retain(|_, imported_at| *imported_at >= cutoff);
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.current
+            .iter()
+            .map(|current| &current.entry)
+            .chain(self.pending.iter())
+    }
+}
+
+// what the video request handler should do next
+pub(crate) enum Next {
+    Request(QueueEntry),
+    // walk through the parked requests, see review::run_session
+    Review,
+}
+
+// Shared between the http server (producer) and the video request handler (consumer).
+// Unlike a bare mpsc channel, its contents can be inspected,
+// which lets us mirror them to the (optional) queue file after every change.
+pub(crate) struct VideoRequestQueue {
+    state: Mutex<QueueState>,
+    notify: tokio::sync::Notify,
+    // bumped on every change, for the background workers (see prepare::Preparer)
+    changes: tokio::sync::watch::Sender<()>,
+    // set by 'stop --now', the tty drops the current request (killing its commands) and exits
+    stopped_now: tokio::sync::watch::Sender<bool>,
+    capacity: usize,
+    queue_file: Option<PathBuf>,
+    // finished requests get appended to it, see history::HistoryEntry
+    history_file: Option<PathBuf>,
+}
+
+impl VideoRequestQueue {
+    pub(crate) fn new(
+        capacity: usize,
+        queue_file: Option<PathBuf>,
+        history_file: Option<PathBuf>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut state = QueueState::default();
+
+        if let Some(history_file) = history_file.as_deref() {
+            match history::read(history_file) {
+                Ok(entries) => {
+                    for entry in entries.into_iter().filter(|entry| entry.outcome == Outcome::Imported) {
+                        state.record_imported(entry.source_url, entry.finished_at);
+                    }
+                }
+                Err(err) => eprintln!(
+                    "{} '{}', videos imported by earlier tty sessions won't be warned about: {err}",
+                    style("Failed to read the history file").for_stderr().red(),
+                    history_file.display()
+                ),
+            }
+        }
+
+        if let Some(queue_file) = queue_file.as_deref() {
+            let contents = read_queue_file(queue_file)?.unwrap_or_default();
+            let entries = parse_queue_file(&contents, |line, err| {
+                anyhow::bail!("Invalid queue file entry at line {line}: {err}")
+            })?;
+            state.pending = state.adopt_entries(entries);
+
+            if !state.pending.is_empty() {
+                println!(
+                    "{} {} unfinished video request(s) from '{}'",
+                    style("Restored").green(),
+                    state.pending.len(),
+                    queue_file.display()
+                );
+            }
+        }
+
+        Ok(Self {
+            state: Mutex::new(state),
+            notify: tokio::sync::Notify::new(),
+            changes: tokio::sync::watch::Sender::new(()),
+            stopped_now: tokio::sync::watch::Sender::new(false),
+            capacity,
+            queue_file,
+            history_file,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // a panic while holding the lock can't leave the state half-updated, so ignore poisoning
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // returns the id of the new queue entry
+    pub(crate) fn try_push(&self, vreq: VideoRequest) -> Result<u64, QueuePushError> {
+        let mut state = self.lock();
+        if state.stopping {
+            return Err(QueuePushError::Stopping(vreq));
+        }
+        if state.entries().any(|queued| queued.request.same_target(&vreq)) {
+            return Err(QueuePushError::Duplicate(vreq));
+        }
+        if state.pending.len() >= self.capacity {
+            return Err(QueuePushError::Full(vreq));
+        }
+
+        let entry = state.new_entry(vreq);
+        let id = entry.id;
+        state.pending.push_back(entry);
+        self.persist(&mut state);
+        drop(state);
+
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    // all or nothing would make a single bad url in a batch reject the good ones, so each request gets its own result
+    // priority requests go to the front of the queue, keeping the batch's order
+    pub(crate) fn try_push_batch(&self, vreqs: Vec<VideoRequest>, priority: bool) -> Vec<Result<u64, QueuePushError>> {
+        let mut state = self.lock();
+        let mut results = Vec::with_capacity(vreqs.len());
+        let mut pushed = 0;
+        for vreq in vreqs {
+            if state.stopping {
+                results.push(Err(QueuePushError::Stopping(vreq)));
+            } else if state.entries().any(|queued| queued.request.same_target(&vreq)) {
+                results.push(Err(QueuePushError::Duplicate(vreq)));
+            } else if state.pending.len() >= self.capacity {
+                results.push(Err(QueuePushError::Full(vreq)));
+            } else {
+                let entry = state.new_entry(vreq);
+                results.push(Ok(entry.id));
+                if priority {
+                    state.pending.insert(pushed, entry);
+                } else {
+                    state.pending.push_back(entry);
+                }
+                pushed += 1;
+            }
+        }
+
+        if results.iter().any(Result::is_ok) {
+            self.persist(&mut state);
+            drop(state);
+
+            self.notify.notify_one();
+        }
+
+        results
+    }
+
+    // used for requests that expand into other requests, which should be handled right away (ignores the capacity)
+    pub(crate) fn push_front(&self, vreqs: Vec<VideoRequest>) -> Vec<u64> {
+        let mut state = self.lock();
+        let entries: Vec<QueueEntry> = vreqs.into_iter().map(|vreq| state.new_entry(vreq)).collect();
+        let ids = entries.iter().map(|entry| entry.id).collect();
+        for entry in entries.into_iter().rev() {
+            state.pending.push_front(entry);
+        }
+        self.persist(&mut state);
+        drop(state);
+
+        self.notify.notify_one();
+        ids
+    }
+
+    pub(crate) fn remove(&self, id: u64) -> Result<VideoRequest, QueueEditError> {
+        let mut state = self.lock();
+        let index = Self::pending_index(&state, id)?;
+        let entry = state
+            .pending
+            .remove(index)
+            .expect("index comes from the pending requests");
+        let history_entry = history_entry(&entry, None, Vec::new(), Outcome::Cancelled, &RequestReport::default());
+        state.record_finished(id, Outcome::Cancelled, RequestReport::default());
+        self.persist(&mut state);
+        drop(state);
+
+        self.append_history(&history_entry);
+        Ok(entry.request)
+    }
+
+    pub(crate) fn move_to_front(&self, id: u64) -> Result<(), QueueEditError> {
+        let mut state = self.lock();
+        let index = Self::pending_index(&state, id)?;
+        let entry = state
+            .pending
+            .remove(index)
+            .expect("index comes from the pending requests");
+        state.pending.push_front(entry);
+        self.persist(&mut state);
+
+        Ok(())
+    }
+
+    fn pending_index(state: &QueueState, id: u64) -> Result<usize, QueueEditError> {
+        if state.current.as_ref().is_some_and(|current| current.entry.id == id) {
+            return Err(QueueEditError::Current(id));
+        }
+
+        state
+            .pending
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or(QueueEditError::NotFound(id))
+    }
+
+    // waits for the next pending request, marking it as the one currently being handled
+    // none once the tty is stopping
+    pub(crate) async fn next(&self) -> Option<Next> {
+        loop {
+            {
+                let mut state = self.lock();
+                if state.stopping {
+                    return None;
+                }
+
+                if state.review_requested {
+                    state.review_requested = false;
+                    return Some(Next::Review);
+                }
+
+                if let Some(entry) = state.pending.pop_front() {
+                    events::emit(Event::RequestStarted {
+                        id: entry.id,
+                        source_url: entry.request.source_url(),
+                    });
+                    state.current = Some(CurrentEntry {
+                        entry: entry.clone(),
+                        stage: Stage::Starting,
+                        started_at: unix_now(),
+                        stages: Vec::new(),
+                    });
+                    self.persist(&mut state);
+                    return Some(Next::Request(entry));
+                }
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    // returns false if one was already requested
+    pub(crate) fn request_review(&self) -> bool {
+        let already_requested = std::mem::replace(&mut self.lock().review_requested, true);
+        self.notify.notify_one();
+        !already_requested
+    }
+
+    // the pending requests stay in the queue (file), for the next tty session
+    pub(crate) fn stop_after_current(&self) {
+        self.lock().stopping = true;
+        self.notify.notify_one();
+        self.changes.send_replace(());
+    }
+
+    // the current request starts over in the next tty session, persist the queue first
+    pub(crate) fn stop_now(&self) {
+        self.stop_after_current();
+        self.stopped_now.send_replace(true);
+    }
+
+    pub(crate) async fn stopped_now(&self) {
+        let mut stopped_now = self.stopped_now.subscribe();
+        // the sender lives as long as the queue
+        let _ = stopped_now.wait_for(|stopped_now| *stopped_now).await;
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.lock().stopping
+    }
+
+    // writes the queue file one last time, returns whether there's one
+    pub(crate) fn persist_now(&self) -> bool {
+        let mut state = self.lock();
+        self.persist(&mut state);
+        self.queue_file.is_some()
+    }
+
+    pub(crate) fn set_stage(&self, stage: Stage) {
+        let mut state = self.lock();
+        let Some(current) = state.current.as_mut().filter(|current| current.stage != stage) else {
+            return;
+        };
+
+        let id = current.entry.id;
+        if current.stage != Stage::Starting {
+            events::emit(Event::StageFinished {
+                id,
+                stage: current.stage,
+            });
+        }
+        events::emit(Event::StageStarted { id, stage });
+        current.stage = stage;
+
+        let now = unix_now();
+        if let Some(last) = current.stages.last_mut() {
+            last.finished_at = Some(now);
+        }
+        current.stages.push(StageTiming {
+            stage,
+            started_at: now,
+            finished_at: None,
+        });
+    }
+
+    // the current request is done (either completed or aborted), remove it from the queue file
+    pub(crate) fn finish_current(&self, outcome: Outcome, report: RequestReport) {
+        let mut state = self.lock();
+        let mut finished = None;
+        if let Some(mut current) = state.current.take() {
+            if current.stage != Stage::Starting {
+                events::emit(Event::StageFinished {
+                    id: current.entry.id,
+                    stage: current.stage,
+                });
+            }
+            if let Some(last) = current.stages.last_mut() {
+                last.finished_at = Some(unix_now());
+            }
+            finished = Some(history_entry(
+                &current.entry,
+                Some(current.started_at),
+                current.stages,
+                outcome,
+                &report,
+            ));
+            if outcome == Outcome::Imported {
+                state.record_imported(current.entry.request.source_url(), unix_now());
+            }
+            state.record_finished(current.entry.id, outcome, report);
+        }
+        self.persist(&mut state);
+        drop(state);
+
+        if let Some(history_entry) = finished {
+            self.append_history(&history_entry);
+        }
+    }
+
+    // the current request's id, and the first `count` pending requests (none once the tty is stopping)
+    pub(crate) fn upcoming(&self, count: usize) -> (Option<u64>, Vec<QueueEntry>) {
+        let state = self.lock();
+        let current = state.current.as_ref().map(|current| current.entry.id);
+        if state.stopping {
+            return (current, Vec::new());
+        }
+
+        (current, state.pending.iter().take(count).cloned().collect())
+    }
+
+    pub(crate) fn subscribe_changes(&self) -> tokio::sync::watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    // when the same video was last imported by this tty, if it was
+    pub(crate) fn imported_at(&self, vreq: &VideoRequest) -> Option<u64> {
+        self.lock().imported.get(&vreq.source_url()).copied()
+    }
+
+    pub(crate) fn status(&self, id: u64) -> Option<RequestStatus> {
+        let state = self.lock();
+        if let Some(current) = state.current.as_ref().filter(|current| current.entry.id == id) {
+            return Some(RequestStatus::Processing { stage: current.stage });
+        }
+
+        if let Some(position) = state.pending.iter().position(|entry| entry.id == id) {
+            return Some(RequestStatus::Pending { position });
+        }
+
+        state
+            .finished
+            .iter()
+            .rfind(|(finished_id, _, _)| *finished_id == id)
+            .map(|(_, outcome, report)| RequestStatus::Finished {
+                outcome: *outcome,
+                report: report.clone(),
+            })
+    }
+
+    // requests without a profile are reported with the tty's own
+    pub(crate) fn info(&self, default_profile: &str) -> QueueInfo {
+        let state = self.lock();
+        QueueInfo {
+            current: state.current.as_ref().map(|current| CurrentEntryInfo {
+                entry: QueueEntryInfo::new(&current.entry, default_profile),
+                stage: current.stage,
+            }),
+            pending: state
+                .pending
+                .iter()
+                .map(|entry| QueueEntryInfo::new(entry, default_profile))
+                .collect(),
+        }
+    }
+
+    // never called with the state locked, the routes (and the preparer) shouldn't wait for the disk
+    fn append_history(&self, history_entry: &history::HistoryEntry) {
+        let Some(history_file) = self.history_file.as_deref() else {
+            return;
+        };
+
+        if let Err(err) = history::append(history_file, history_entry) {
+            eprintln!(
+                "{} '{}': {err}",
+                style("Failed to write to the history file").for_stderr().red(),
+                history_file.display()
+            );
+        }
+    }
+
+    // every change goes through here, whether there's a queue file or not
+    fn persist(&self, state: &mut QueueState) {
+        self.changes.send_replace(());
+
+        let Some(queue_file) = self.queue_file.as_deref() else {
+            return;
+        };
+
+        let contents = match serialize_queue(state.entries()) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("{}: {err}", style("Failed to serialize queue").for_stderr().red());
+                return;
+            }
+        };
+
+        match write_queue_file(queue_file, &contents) {
+            Ok(()) => state.last_synced = Some(contents),
+            Err(err) => {
+                eprintln!(
+                    "{} '{}': {err}",
+                    style("Failed to write queue file").for_stderr().red(),
+                    queue_file.display()
+                );
+            }
+        }
+    }
+
+    // replaces the pending requests with the ones currently in the queue file (blocks on reading it)
+    fn reconcile_with_queue_file(&self) {
+        let Some(queue_file) = self.queue_file.as_deref() else {
+            return;
+        };
+
+        // the file is read and parsed without the lock, so that the http handlers don't wait for the io
+        let (contents, mut entries, mut state) = loop {
+            let last_synced = self.lock().last_synced.clone();
+            let contents = match read_queue_file(queue_file) {
+                Ok(Some(contents)) => contents,
+                // the file is probably being replaced, we'll get another event once it's back
+                Ok(None) => return,
+                Err(err) => {
+                    eprintln!(
+                        "{} '{}': {err}",
+                        style("Failed to read queue file").for_stderr().red(),
+                        queue_file.display()
+                    );
+                    return;
+                }
+            };
+
+            if last_synced.as_ref() == Some(&contents) {
+                // our own write (or an edit that changed nothing)
+                return;
+            }
+
+            let Ok(entries) = parse_queue_file::<std::convert::Infallible>(&contents, |line, err| {
+                eprintln!(
+                    "{} {line}, skipping it: {err}",
+                    style("Invalid queue file entry at line").for_stderr().red()
+                );
+                Ok(())
+            });
+
+            let state = self.lock();
+            // persist() wrote the file in the meantime, what we read could be what it replaced rather than an
+            // external edit, so read it again
+            if state.last_synced == last_synced {
+                break (contents, entries, state);
+            }
+        };
+
+        // the current request can't be taken back from the handler, so it's not part of the pending ones
+        if let Some(current) = state.current.as_ref()
+            && let Some(index) = entries.iter().position(|entry| match entry.id {
+                Some(id) => id == current.entry.id,
+                None => entry.request == current.entry.request,
+            })
+        {
+            entries.remove(index);
+        }
+
+        let requests = state.adopt_entries(entries);
+
+        if requests.len() > self.capacity {
+            println!(
+                "{} the queue file contains {} pending requests, more than the maximum of {}",
+                style("WARNING:").yellow(),
+                requests.len(),
+                self.capacity
+            );
+        }
+
+        println!(
+            "{} queue from '{}', {} request(s) pending",
+            style("Reloaded").green(),
+            queue_file.display(),
+            requests.len()
+        );
+
+        state.pending = requests;
+        state.last_synced = Some(contents);
+        drop(state);
+
+        self.changes.send_replace(());
+
+        self.notify.notify_one();
+    }
+}
+
+pub(crate) fn spawn_queue_file_watcher(vreq_queue: Arc<VideoRequestQueue>) -> Result<(), anyhow::Error> {
+    use notify::Watcher;
+
+    let Some(queue_file) = vreq_queue.queue_file.as_deref() else {
+        return Ok(());
+    };
+
+    // editors (and our own writes) usually replace the file instead of modifying it in place,
+    // so we need to watch the parent directory instead of the file itself
+    let queue_file = std::path::absolute(queue_file)?;
+    let queue_file_name = queue_file.file_name().map(ToOwned::to_owned);
+    let parent = queue_file
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Queue file '{}' has no parent directory", queue_file.display()))?;
+
+    let (events_send, mut events_receive) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = events_send.send(event);
+    })?;
+    watcher.watch(parent, notify::RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        // keep the watcher alive for as long as the task
+        let _watcher = watcher;
+
+        while let Some(event) = events_receive.recv().await {
+            match event {
+                Ok(event) => {
+                    let is_queue_file = event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == queue_file_name.as_deref());
+                    if !is_queue_file || event.kind.is_access() {
+                        continue;
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{}: {err}", style("Queue file watcher error").for_stderr().red());
+                    continue;
+                }
+            }
+
+            // saving a file usually produces a burst of events, let it settle
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            while events_receive.try_recv().is_ok() {}
+
+            let reconciled = vreq_queue.clone();
+            if let Err(err) = tokio::task::spawn_blocking(move || reconciled.reconcile_with_queue_file()).await {
+                eprintln!("{}: {err}", style("Failed to reload the queue file").for_stderr().red());
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn history_entry(
+    entry: &QueueEntry,
+    started_at: Option<u64>,
+    stages: Vec<StageTiming>,
+    outcome: Outcome,
+    report: &RequestReport,
+) -> history::HistoryEntry {
+    history::HistoryEntry {
+        id: entry.id,
+        source_url: entry.request.source_url(),
+        request: entry.request.clone(),
+        enqueued_at: entry.enqueued_at,
+        started_at,
+        finished_at: unix_now(),
+        stages,
+        outcome,
+        report: report.clone(),
+    }
+}
+
+fn read_queue_file(path: &Path) -> Result<Option<String>, anyhow::Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// one json request per line, blank lines are ignored
+fn parse_queue_file<E>(
+    contents: &str,
+    mut on_invalid_line: impl FnMut(usize, serde_json::Error) -> Result<(), E>,
+) -> Result<Vec<QueueFileEntry>, E> {
+    let mut requests = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(line) {
+            Ok(entry) => requests.push(entry),
+            Err(err) => on_invalid_line(index + 1, err)?,
+        }
+    }
+
+    Ok(requests)
+}
+
+fn serialize_queue<'a>(entries: impl Iterator<Item = &'a QueueEntry>) -> Result<String, serde_json::Error> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+
+    Ok(contents)
+}
+
+fn write_queue_file(path: &Path, contents: &str) -> Result<(), anyhow::Error> {
+    // write to a sibling file and rename it, so that a crash can never leave a truncated queue file behind
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+    let mut file = tempfile::NamedTempFile::new_in(parent.unwrap_or_else(|| Path::new(".")))?;
+    file.write_all(contents.as_bytes())?;
+    file.persist(path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(video_id: &str) -> VideoRequest {
+        VideoRequest::from_yt_url(video_id, 1, None, false).unwrap()
+    }
+
+    fn pending_ids(vreq_queue: &VideoRequestQueue) -> Vec<u64> {
+        vreq_queue
+            .info(crate::config::DEFAULT_PROFILE)
+            .pending
+            .iter()
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    #[test]
+    fn parses_queue_files() {
+        let contents = r#"
+{"id":3,"enqueued_at":100,"site":"youtube","target":"dQw4w9WgXcQ","kind":"video","from_pid":1}
+
+{"target":"lalOy8Mbfdc","from_pid":2}
+not json
+{"youtube_id":"yZv2daTWRZU","from_pid":3,"profile":"music"}
+"#;
+
+        let mut invalid = Vec::new();
+        let entries = parse_queue_file::<std::convert::Infallible>(contents, |line, _err| {
+            invalid.push(line);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(invalid, [5]);
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].id, entries[0].enqueued_at), (Some(3), Some(100)));
+        // hand-written entries can leave out everything but the target and the pid
+        assert_eq!((entries[1].id, entries[1].enqueued_at), (None, None));
+        assert_eq!(
+            entries[1].request,
+            VideoRequest::from_yt_url("lalOy8Mbfdc", 2, None, false).unwrap()
+        );
+        // queue files written before other sites were supported
+        assert_eq!(entries[2].request.target, "yZv2daTWRZU");
+        assert_eq!(entries[2].request.profile.as_deref(), Some("music"));
+
+        assert!(parse_queue_file(contents, |line, _err| Err(line)).is_err());
+    }
+
+    #[test]
+    fn adopted_entries_get_unique_ids() {
+        let mut state = QueueState::default();
+        let entries = parse_queue_file::<std::convert::Infallible>(
+            concat!(
+                r#"{"target":"dQw4w9WgXcQ","from_pid":1}"#,
+                "\n",
+                r#"{"id":7,"target":"lalOy8Mbfdc","from_pid":1}"#,
+                "\n",
+                r#"{"id":7,"target":"yZv2daTWRZU","from_pid":1}"#,
+                "\n",
+            ),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+
+        let ids: Vec<u64> = state.adopt_entries(entries).iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [8, 7, 9]);
+        assert_eq!(state.new_entry(request("0zM3nApSvMg")).id, 10);
+    }
+
+    #[test]
+    fn queue_file_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue_file = dir.path().join("queue.jsonl");
+
+        let vreq_queue = VideoRequestQueue::new(16, Some(queue_file.clone()), None).unwrap();
+        let first = vreq_queue.try_push(request("dQw4w9WgXcQ")).unwrap();
+        let second = vreq_queue.try_push(request("lalOy8Mbfdc")).unwrap();
+        vreq_queue.remove(first).unwrap();
+        drop(vreq_queue);
+
+        let restored = VideoRequestQueue::new(16, Some(queue_file.clone()), None).unwrap();
+        assert_eq!(pending_ids(&restored), [second]);
+        // ids keep going up after a restart
+        assert!(restored.try_push(request("yZv2daTWRZU")).unwrap() > second);
+
+        std::fs::write(&queue_file, "not json\n").unwrap();
+        assert!(VideoRequestQueue::new(16, Some(queue_file), None).is_err());
+    }
+
+    #[test]
+    fn reconciles_external_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue_file = dir.path().join("queue.jsonl");
+        let vreq_queue = VideoRequestQueue::new(16, Some(queue_file.clone()), None).unwrap();
+        let first = vreq_queue.try_push(request("dQw4w9WgXcQ")).unwrap();
+        let second = vreq_queue.try_push(request("lalOy8Mbfdc")).unwrap();
+
+        // our own write
+        vreq_queue.reconcile_with_queue_file();
+        assert_eq!(pending_ids(&vreq_queue), [first, second]);
+
+        // reordered, one removed by hand and one added by hand
+        let contents = std::fs::read_to_string(&queue_file).unwrap();
+        let second_line = contents.lines().nth(1).unwrap();
+        std::fs::write(
+            &queue_file,
+            format!("{second_line}\n{{\"target\":\"yZv2daTWRZU\",\"from_pid\":1}}\nnot json\n"),
+        )
+        .unwrap();
+        vreq_queue.reconcile_with_queue_file();
+        let ids = pending_ids(&vreq_queue);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0], second);
+        assert!(ids[1] > second);
+
+        // pushes after a reload are our own writes again
+        let third = vreq_queue.try_push(request("0zM3nApSvMg")).unwrap();
+        vreq_queue.reconcile_with_queue_file();
+        assert_eq!(pending_ids(&vreq_queue), [ids[0], ids[1], third]);
+    }
+
+    #[test]
+    fn refuses_requests_once_stopping() {
+        let vreq_queue = VideoRequestQueue::new(4, None, None).unwrap();
+        vreq_queue.try_push(request("dQw4w9WgXcQ")).unwrap();
+        vreq_queue.stop_after_current();
+
+        assert!(matches!(
+            vreq_queue.try_push(request("lalOy8Mbfdc")),
+            Err(QueuePushError::Stopping(_))
+        ));
+        let results = vreq_queue.try_push_batch(vec![request("yZv2daTWRZU"), request("0zM3nApSvMg")], true);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(QueuePushError::Stopping(_)))));
+        assert_eq!(pending_ids(&vreq_queue).len(), 1);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::cli;
+use crate::client::TtyClient;
+use crate::queue::{QueueEntryInfo, QueueInfo};
+use crate::utils::time::{format_age, unix_now};
+use console::style;
+use std::time::Duration;
+
+// Shows what the tty instance is working on, and what's waiting for it.
+pub(crate) async fn run(args: cli::QueueArgs) -> Result<(), anyhow::Error> {
+    let client = TtyClient::connect(&args.connection).await?;
+    let response = client.get("/queue").timeout(Duration::from_secs(1)).send().await?;
+    let queue: QueueInfo = client
+        .error_for_status(response, "couldn't list the queue")
+        .await?
+        .json()
+        .await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&queue)?);
+        return Ok(());
+    }
+
+    let now = unix_now();
+    let describe = |entry: &QueueEntryInfo| {
+        format!(
+            "{} {} (pid {}, profile: {}, enqueued {} ago)",
+            style(format!("#{}", entry.id)).cyan(),
+            entry.source_url,
+            entry.from_pid,
+            entry.profile,
+            format_age(now.saturating_sub(entry.enqueued_at))
+        )
+    };
+
+    match &queue.current {
+        Some(current) => println!(
+            "{} {}: {}",
+            style("Current").green(),
+            style(current.stage).yellow(),
+            describe(&current.entry)
+        ),
+        None => println!("{}", style("No video request is being handled").green()),
+    }
+
+    if queue.pending.is_empty() {
+        println!("No pending video requests");
+    } else {
+        println!("{} video request(s) pending:", queue.pending.len());
+        for entry in &queue.pending {
+            println!("  {}", describe(entry));
+        }
+    }
+
+    Ok(())
+}
@@ -1,63 +1,420 @@
+use crate::cli;
+use crate::client::TtyClient;
+use crate::net::BatchItemResult;
+use crate::queue::RequestStatus;
+use crate::utils::time::{format_age, unix_now};
 use crate::video;
-use crate::{cli, lock};
+use crate::video::{Outcome, RequestReport};
 use anyhow::anyhow;
-use std::net::SocketAddr;
+use console::style;
+use std::path::Path;
+use std::process::ExitCode;
 use std::time::Duration;
 
-pub(crate) async fn run(args: cli::RequestArgs) -> Result<(), anyhow::Error> {
-    let port = match (args.port_override, args.dangerously_skip_lock_checks) {
-        (None, false) => {
-            tokio::task::spawn_blocking(|| {
-                lock::ensure_tty_running_and_read_port().map_err(|err| {
-                    anyhow!(
-                        "Failed to read tty port from portfile! Is the tty instance running?\n{}",
-                        err
-                    )
-                })
-            })
-            .await??
-        }
-        (None, true) => {
-            return Err(anyhow!(
-                "ERROR: The lockfile check is set to be skipped, but no port has been specified!"
-            ));
-        }
-        (Some(port), skip) => {
-            if skip {
-                println!("WARNING: Skipping lock check!");
+pub(crate) async fn run(args: cli::RequestArgs) -> Result<ExitCode, anyhow::Error> {
+    if !args.cancel.is_empty() || !args.move_to_front.is_empty() {
+        edit_queue(&args).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let inputs = collect_inputs(&args)?;
+    let total = inputs.len();
+
+    let client = TtyClient::connect(&args.connection).await?;
+
+    println!("Creating {total} video request(s)...");
+    let mut rejected = 0;
+    let mut accepted = Vec::new();
+    let mut sent_inputs = Vec::with_capacity(total);
+    let mut video_requests = Vec::with_capacity(total);
+    for input in inputs {
+        let video_request = if input.youtube {
+            video::VideoRequest::from_yt_url(&input.url, std::process::id(), args.profile.clone(), args.playlist)
+        } else {
+            video::VideoRequest::from_url(&input.url, std::process::id(), args.profile.clone(), args.playlist)
+        };
+
+        match video_request {
+            Ok(video_request) => {
+                sent_inputs.push(input.url);
+                video_requests.push(video_request);
+            }
+            Err(err) => {
+                rejected += 1;
+                eprintln!("{} {}: {err}", style("Invalid").for_stderr().red(), input.url);
             }
-            println!("Using manually specified port {}", port);
-            port
         }
-    };
+    }
+
+    if !video_requests.is_empty() {
+        let (batch_accepted, batch_rejected) =
+            send_batch(&client, &sent_inputs, &video_requests, args.priority).await?;
+        accepted = batch_accepted;
+        rejected += batch_rejected;
+    }
 
-    let tty_addr = format!("127.0.0.1:{}", port).parse::<SocketAddr>()?;
-    let client = reqwest::Client::builder()
-        .build()
-        .map_err(|err| anyhow!("Failed to create http client!\n{}", err))?;
+    let rejected_error = anyhow!("{rejected} of {total} video request(s) couldn't be enqueued");
+    if !args.wait {
+        return match rejected {
+            0 => Ok(ExitCode::SUCCESS),
+            _ => Err(rejected_error),
+        };
+    }
+
+    let mut worst = match accepted.is_empty() {
+        true => WaitOutcome::Imported,
+        false => wait_for_outcomes(&client, accepted).await?,
+    };
+    // the outcomes of the enqueued requests still get their exit code
+    if rejected > 0 {
+        eprintln!("{}", style(rejected_error).for_stderr().red());
+        worst = worst.max(WaitOutcome::Rejected);
+    }
 
-    println!("Creating video request...");
-    let video_request = video::VideoRequest::from_yt_url(&args.yt_url, std::process::id())?;
-    let yt_id = &video_request.youtube_id;
+    Ok(worst.exit_code())
+}
 
-    println!("Sending request to tty on {:?}", tty_addr);
+// sends the video requests (made from `urls`) in a single batch, printing what the tty did with each of them,
+// returns the (url, id) of the accepted ones and how many got rejected
+pub(crate) async fn send_batch(
+    client: &TtyClient,
+    urls: &[String],
+    video_requests: &[video::VideoRequest],
+    priority: bool,
+) -> Result<(Vec<(String, u64)>, usize), anyhow::Error> {
+    println!(
+        "Sending {} request(s) to tty on {}",
+        video_requests.len(),
+        client.endpoint
+    );
     let response = client
-        .post(format!("http://127.0.0.1:{}/video-request", port))
-        .form(&video_request)
+        .post("/video-requests")
+        .query(&[("priority", priority)])
+        .json(video_requests)
         .timeout(Duration::from_secs(1))
         .send()
         .await?;
+    let response = client.error_for_status(response, "refused the batch").await?;
+
+    let results: Vec<BatchItemResult> = response.json().await?;
+    if results.len() != urls.len() {
+        return Err(anyhow!(
+            "TTY ({}) answered with {} result(s) for {} request(s)",
+            client.endpoint,
+            results.len(),
+            urls.len()
+        ));
+    }
+
+    let mut accepted = Vec::with_capacity(urls.len());
+    let mut rejected = 0;
+    for (url, result) in urls.iter().zip(results) {
+        let reason = match result {
+            BatchItemResult::Accepted { id, imported_at } => {
+                println!("{} {url} (id: {id})", style("Enqueued").green());
+                if let Some(imported_at) = imported_at {
+                    println!(
+                        "{} {url} was already imported {} ago",
+                        style("WARNING:").yellow(),
+                        format_age(unix_now().saturating_sub(imported_at))
+                    );
+                }
+                accepted.push((url.clone(), id));
+                continue;
+            }
+            BatchItemResult::QueueFull => String::from("Video request queue capacity exceeded"),
+            BatchItemResult::Duplicate => String::from("Already in the queue"),
+            BatchItemResult::Rejected { reason } => reason,
+        };
+
+        rejected += 1;
+        eprintln!("{} {url}: {reason}", style("Rejected").for_stderr().red());
+    }
+
+    Ok((accepted, rejected))
+}
+
+// how the waited for requests ended, from the least to the most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum WaitOutcome {
+    Imported,
+    // some urls couldn't be enqueued
+    Rejected,
+    // the tty forgot about the request (it only remembers the last finished ones, and nothing once restarted)
+    Unknown,
+    Cancelled,
+    NeedsReview,
+    Aborted,
+    Failed,
+}
+
+impl WaitOutcome {
+    // clear of 1 (any other error) and 2 (clap's usage errors)
+    fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            WaitOutcome::Imported => 0,
+            WaitOutcome::Aborted => 10,
+            WaitOutcome::Failed => 11,
+            WaitOutcome::Cancelled => 12,
+            WaitOutcome::NeedsReview => 13,
+            WaitOutcome::Rejected => 14,
+            WaitOutcome::Unknown => 15,
+        })
+    }
+}
+
+impl From<Outcome> for WaitOutcome {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Imported | Outcome::Expanded => WaitOutcome::Imported,
+            Outcome::Cancelled => WaitOutcome::Cancelled,
+            Outcome::NeedsReview => WaitOutcome::NeedsReview,
+            Outcome::Aborted => WaitOutcome::Aborted,
+            Outcome::Failed => WaitOutcome::Failed,
+        }
+    }
+}
+
+// polls the tty until every request is done, returning the most severe outcome
+async fn wait_for_outcomes(client: &TtyClient, mut waiting: Vec<(String, u64)>) -> Result<WaitOutcome, anyhow::Error> {
+    println!("Waiting for {} video request(s)...", waiting.len());
+
+    let mut worst = WaitOutcome::Imported;
+    while !waiting.is_empty() {
+        tokio::time::sleep(Duration::from_millis(500)).await;
 
-    if response.status().is_success() {
+        let mut still_waiting = Vec::with_capacity(waiting.len());
+        for (url, id) in waiting {
+            let Some(status) = poll_status(client, id).await? else {
+                println!(
+                    "{} {url} (id: {id}): the tty doesn't know about it anymore",
+                    style("Unknown outcome").yellow()
+                );
+                worst = worst.max(WaitOutcome::Unknown);
+                continue;
+            };
+
+            let RequestStatus::Finished { outcome, report } = status else {
+                still_waiting.push((url, id));
+                continue;
+            };
+
+            print_outcome(&url, id, outcome, &report);
+            worst = worst.max(WaitOutcome::from(outcome));
+
+            // playlists are only done once their entries are
+            still_waiting.extend(
+                report
+                    .enqueued
+                    .iter()
+                    .map(|&entry_id| (format!("{url} (entry {entry_id})"), entry_id)),
+            );
+        }
+
+        waiting = still_waiting;
+    }
+
+    Ok(worst)
+}
+
+// None if the tty doesn't know about the request, connection errors are retried (the tty might be restarting)
+async fn poll_status(client: &TtyClient, id: u64) -> Result<Option<RequestStatus>, anyhow::Error> {
+    const ATTEMPTS: u32 = 5;
+
+    let mut attempt = 1;
+    let response = loop {
+        match client
+            .get(&format!("/queue/{id}"))
+            .timeout(Duration::from_secs(1))
+            .send()
+            .await
+        {
+            Ok(response) => break response,
+            Err(err) if attempt < ATTEMPTS && (err.is_connect() || err.is_timeout()) => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let status = client
+        .error_for_status(response, &format!("lost track of request {id}"))
+        .await?
+        .json()
+        .await?;
+    Ok(Some(status))
+}
+
+pub(crate) fn print_outcome(url: &str, id: u64, outcome: Outcome, report: &RequestReport) {
+    let outcome_display = match outcome {
+        Outcome::Imported | Outcome::Expanded => style(outcome).green(),
+        Outcome::Aborted | Outcome::Cancelled | Outcome::NeedsReview => style(outcome).yellow(),
+        Outcome::Failed => style(outcome).red(),
+    };
+
+    match &report.error {
+        Some(error) => println!("{outcome_display} {url} (id: {id}): {error}"),
+        None => println!("{outcome_display} {url} (id: {id})"),
+    }
+
+    for recording in &report.recordings {
+        println!(
+            "  Recording: {} - {} ({})",
+            recording.artist, recording.title, recording.id
+        );
+    }
+    for path in &report.imported_paths {
+        println!("  File: {}", path.display());
+    }
+    if let Some(work_dir) = &report.work_dir {
+        println!("  Kept: {}", work_dir.display());
+    }
+    if outcome == Outcome::Expanded {
+        println!(
+            "  Enqueued {} video request(s) from the playlist",
+            report.enqueued.len()
+        );
+    }
+}
+
+// '--cancel' and '--move-to-front' act on requests that are already in the queue
+async fn edit_queue(args: &cli::RequestArgs) -> Result<(), anyhow::Error> {
+    let client = TtyClient::connect(&args.connection).await?;
+
+    for id in &args.cancel {
+        let response = client
+            .delete(&format!("/queue/{id}"))
+            .timeout(Duration::from_secs(1))
+            .send()
+            .await?;
+        client.error_for_status(response, "couldn't cancel the request").await?;
+        println!("{} video request {id}", style("Cancelled").green());
+    }
+
+    for id in &args.move_to_front {
+        let response = client
+            .post(&format!("/queue/{id}/front"))
+            .timeout(Duration::from_secs(1))
+            .send()
+            .await?;
+        client.error_for_status(response, "couldn't move the request").await?;
+        println!(
+            "{} video request {id} to the front of the queue",
+            style("Moved").green()
+        );
+    }
+
+    Ok(())
+}
+
+struct RequestInput {
+    url: String,
+    // came from '--yt-url', so it must be a YouTube url
+    youtube: bool,
+}
+
+// gathers every url before talking to the tty, so that they can all be sent in a single batch
+fn collect_inputs(args: &cli::RequestArgs) -> Result<Vec<RequestInput>, anyhow::Error> {
+    let mut inputs = Vec::new();
+    // stdin can only be read once, later '-' just don't add anything
+    let mut stdin_read = false;
+
+    let mut read_list = |path: &Path, inputs: &mut Vec<RequestInput>| -> Result<(), anyhow::Error> {
+        let contents = if path == Path::new("-") {
+            if std::mem::replace(&mut stdin_read, true) {
+                return Ok(());
+            }
+            std::io::read_to_string(std::io::stdin())
+                .map_err(|err| anyhow!("Failed to read urls from stdin!\n{err}"))?
+        } else {
+            std::fs::read_to_string(path)
+                .map_err(|err| anyhow!("Failed to read urls from '{}'!\n{err}", path.display()))?
+        };
+
+        inputs.extend(parse_url_list(&contents).map(|url| RequestInput {
+            url: url.to_string(),
+            youtube: false,
+        }));
         Ok(())
-    } else {
-        Err(anyhow!(
-            "TTY ({tty_addr:?}) for {yt_id} (http code: {}); {}",
-            response.status(),
-            response
-                .text()
-                .await
-                .unwrap_or_else(|_| String::from("<unable to decode tty response>")),
-        ))
+    };
+
+    for url in &args.urls {
+        if url == "-" {
+            read_list(Path::new("-"), &mut inputs)?;
+        } else {
+            inputs.push(RequestInput {
+                url: url.clone(),
+                youtube: false,
+            });
+        }
+    }
+
+    inputs.extend(args.yt_url.iter().map(|url| RequestInput {
+        url: url.clone(),
+        youtube: true,
+    }));
+    inputs.extend(args.url.iter().map(|url| RequestInput {
+        url: url.clone(),
+        youtube: false,
+    }));
+
+    for path in &args.from_file {
+        read_list(path, &mut inputs)?;
+    }
+
+    if inputs.is_empty() {
+        return Err(anyhow!("No urls to request!"));
+    }
+
+    Ok(inputs)
+}
+
+// one url per line, empty lines and '#' comments are skipped
+fn parse_url_list(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exits_with_the_most_severe_outcome() {
+        let outcomes = [
+            (vec![Outcome::Imported, Outcome::Expanded], WaitOutcome::Imported, 0),
+            (vec![Outcome::Cancelled, Outcome::Imported], WaitOutcome::Cancelled, 12),
+            (
+                vec![Outcome::Cancelled, Outcome::NeedsReview],
+                WaitOutcome::NeedsReview,
+                13,
+            ),
+            (vec![Outcome::NeedsReview, Outcome::Aborted], WaitOutcome::Aborted, 10),
+            (vec![Outcome::Failed, Outcome::Aborted], WaitOutcome::Failed, 11),
+        ];
+
+        for (outcomes, expected, exit_code) in outcomes {
+            let worst = outcomes
+                .iter()
+                .map(|&outcome| WaitOutcome::from(outcome))
+                .max()
+                .unwrap();
+            assert_eq!(worst, expected, "{outcomes:?}");
+            assert_eq!(worst.exit_code(), ExitCode::from(exit_code), "{outcomes:?}");
+        }
+
+        // rejected urls and forgotten requests don't hide how the others ended
+        assert_eq!(WaitOutcome::Imported.max(WaitOutcome::Rejected), WaitOutcome::Rejected);
+        assert_eq!(WaitOutcome::Rejected.max(WaitOutcome::Unknown), WaitOutcome::Unknown);
+        assert_eq!(WaitOutcome::Unknown.max(WaitOutcome::Cancelled), WaitOutcome::Cancelled);
+        assert_eq!(WaitOutcome::Rejected.exit_code(), ExitCode::from(14));
+        assert_eq!(WaitOutcome::Unknown.exit_code(), ExitCode::from(15));
     }
 }
@@ -0,0 +1,401 @@
+use crate::cli;
+use crate::client::TtyClient;
+use crate::config::Profiles;
+use crate::fingerprinting::file::{self, FileOutcome, LookedUpFile};
+use crate::net::{HttpMsgResponse, TtyAxumState};
+use crate::queue::VideoRequestQueue;
+use crate::user::{self, WhatToDo};
+use crate::utils::time::{format_age, unix_now};
+use crate::video::{self, ReportedRecording, RequestReport, VideoRequest};
+use anyhow::anyhow;
+use console::style;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// A video request that an unattended tty ('--headless' or '--defer-review') stopped at the selection tree.
+// Its work dir is kept, the review picks up from there.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct ParkedRequest {
+    pub(crate) request: VideoRequest,
+    // unix timestamp (seconds)
+    pub(crate) parked_at: u64,
+    pub(crate) reason: String,
+    pub(crate) work_dir: PathBuf,
+    // the files that got tagged before parking
+    pub(crate) recordings: Vec<ReportedRecording>,
+    // the ones waiting for a decision
+    pub(crate) files: Vec<LookedUpFile>,
+}
+
+// what `GET /review` answers with, for each parked request
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct ParkedInfo {
+    pub(crate) name: String,
+    pub(crate) source_url: String,
+    pub(crate) profile: Option<String>,
+    pub(crate) parked_at: u64,
+    pub(crate) reason: String,
+    pub(crate) work_dir: PathBuf,
+    pub(crate) files: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct ReviewStarted {
+    pub(crate) parked: usize,
+}
+
+// Parked requests outlive the tty session (one json file each), unlike the runtime dir they're not cleaned up on logout.
+fn parked_dir(instance: &str) -> Result<PathBuf, anyhow::Error> {
+    let parked_dir = dirs::data_local_dir()
+        .ok_or_else(|| anyhow!("Couldn't find the local data directory!"))?
+        .join("yt-dlp-wrapper")
+        .join("review")
+        .join(instance);
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&parked_dir)
+        .map_err(|err| anyhow!("Failed to create '{}'!\n{err}", parked_dir.display()))?;
+
+    Ok(parked_dir)
+}
+
+// named after the work dir, which is unique
+pub(crate) fn park(instance: &str, parked: &ParkedRequest) -> Result<(), anyhow::Error> {
+    let name = parked
+        .work_dir
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid work directory '{}'", parked.work_dir.display()))?
+        .to_string_lossy()
+        .into_owned();
+
+    let path = parked_dir(instance)?.join(format!("{name}.json"));
+    std::fs::write(&path, serde_json::to_string(parked)?)
+        .map_err(|err| anyhow!("Failed to write '{}'!\n{err}", path.display()))
+}
+
+fn unpark(instance: &str, name: &str) -> Result<(), anyhow::Error> {
+    Ok(std::fs::remove_file(
+        parked_dir(instance)?.join(format!("{name}.json")),
+    )?)
+}
+
+// oldest first, files that can't be read are reported and skipped
+pub(crate) fn list(instance: &str) -> Result<Vec<(String, ParkedRequest)>, anyhow::Error> {
+    let mut parked = Vec::new();
+    for dir_entry in std::fs::read_dir(parked_dir(instance)?)? {
+        let path = dir_entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+        else {
+            continue;
+        };
+
+        let contents = std::fs::read_to_string(&path)?;
+        match serde_json::from_str::<ParkedRequest>(&contents) {
+            Ok(request) => parked.push((String::from(name), request)),
+            Err(err) => eprintln!(
+                "{} invalid parked request '{}': {err}",
+                style("Skipping").for_stderr().yellow(),
+                path.display()
+            ),
+        }
+    }
+
+    parked.sort_by_key(|(_, request)| request.parked_at);
+    Ok(parked)
+}
+
+fn info(name: String, parked: ParkedRequest) -> ParkedInfo {
+    ParkedInfo {
+        name,
+        source_url: parked.request.source_url(),
+        profile: parked.request.profile,
+        parked_at: parked.parked_at,
+        reason: parked.reason,
+        work_dir: parked.work_dir,
+        files: parked.files.into_iter().map(|file| file.filename).collect(),
+    }
+}
+
+pub(crate) async fn list_parked(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+) -> Result<axum::Json<Vec<ParkedInfo>>, HttpMsgResponse> {
+    let instance = state.profiles.selected().instance.clone();
+    let parked = tokio::task::spawn_blocking(move || list(&instance))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+        .map_err(|err| {
+            HttpMsgResponse::new(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list the parked video requests! {err}"),
+            )
+        })?;
+
+    Ok(axum::Json(
+        parked.into_iter().map(|(name, parked)| info(name, parked)).collect(),
+    ))
+}
+
+pub(crate) async fn start_review(
+    axum::extract::State(state): axum::extract::State<TtyAxumState>,
+) -> Result<(axum::http::StatusCode, axum::Json<ReviewStarted>), HttpMsgResponse> {
+    let args = state.profiles.selected();
+    // nobody would see the prompts
+    if args.headless {
+        return Err(HttpMsgResponse::new(
+            axum::http::StatusCode::CONFLICT,
+            String::from(
+                "The tty instance is headless, restart it without '--headless' (e.g. with '--defer-review') to review!",
+            ),
+        ));
+    }
+
+    let instance = args.instance.clone();
+    let parked = tokio::task::spawn_blocking(move || list(&instance).map(|parked| parked.len()))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+        .map_err(|err| {
+            HttpMsgResponse::new(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list the parked video requests! {err}"),
+            )
+        })?;
+
+    if state.queue.request_review() {
+        println!("{}", style("Review session requested").magenta());
+    }
+
+    Ok((axum::http::StatusCode::ACCEPTED, axum::Json(ReviewStarted { parked })))
+}
+
+enum ReviewChoice {
+    Review,
+    Skip,
+    Discard,
+    End,
+}
+
+// Walks through the parked requests one by one, in the tty's terminal.
+pub(crate) async fn run_session(
+    profiles: &Profiles,
+    vreq_queue: &VideoRequestQueue,
+    acoustid_client: &mut reqwest::Client,
+) -> Result<(), anyhow::Error> {
+    let instance = profiles.selected().instance.clone();
+    let parked = list(&instance)?;
+    if parked.is_empty() {
+        println!("{}", style("No parked video request to review").magenta());
+        return Ok(());
+    }
+
+    let _attended = user::attend();
+    let total = parked.len();
+    println!(
+        "{} {total} parked video request(s)",
+        style("Reviewing").magenta().bold()
+    );
+
+    for (index, (name, parked)) in parked.into_iter().enumerate() {
+        println!(
+            "\n[{}/{total}] {} (profile: {}, parked {} ago)\n{}: {}",
+            index + 1,
+            style(parked.request.source_url()).cyan(),
+            parked
+                .request
+                .profile
+                .as_deref()
+                .unwrap_or(&profiles.selected().profile),
+            format_age(unix_now().saturating_sub(parked.parked_at)),
+            style("Reason").bold(),
+            parked.reason
+        );
+
+        let choice = user::interact("Review the parked video request?", move || {
+            dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                .with_prompt("What would you like to do with it?")
+                .items(&[
+                    "Review it now",
+                    "Leave it parked",
+                    "Discard it (deletes its temp directory)",
+                    "End the review session",
+                ])
+                .default(0)
+                .interact()
+                .map(|index| match index {
+                    0 => ReviewChoice::Review,
+                    1 => ReviewChoice::Skip,
+                    2 => ReviewChoice::Discard,
+                    _ => ReviewChoice::End,
+                })
+        })
+        .await??;
+
+        match choice {
+            ReviewChoice::Review => {}
+            ReviewChoice::Skip => continue,
+            ReviewChoice::Discard => {
+                if let Err(err) = std::fs::remove_dir_all(&parked.work_dir) {
+                    eprintln!(
+                        "{} '{}': {err}",
+                        style("Failed to delete").for_stderr().red(),
+                        parked.work_dir.display()
+                    );
+                }
+                unpark(&instance, &name)?;
+                println!("{} {}", style("Discarded").yellow(), parked.request.source_url());
+                continue;
+            }
+            ReviewChoice::End => break,
+        }
+
+        let Some(args) = profiles.get(parked.request.profile.as_deref()) else {
+            eprintln!(
+                "{} unknown profile '{}', leaving it parked",
+                style("Can't review it:").for_stderr().red(),
+                parked.request.profile.as_deref().unwrap_or_default()
+            );
+            continue;
+        };
+
+        match review_request(&parked, args, vreq_queue, acoustid_client).await {
+            Ok(Some(report)) => {
+                unpark(&instance, &name)?;
+                println!("{} {}", style("Imported").green(), parked.request.source_url());
+                for path in &report.imported_paths {
+                    println!("  File: {}", path.display());
+                }
+            }
+            Ok(None) => println!(
+                "{} {}, leaving it parked",
+                style("Aborted").yellow(),
+                parked.request.source_url()
+            ),
+            Err(err) => eprintln!(
+                "{} leaving it parked\n{err}",
+                style("Failed to review the video request!").for_stderr().red()
+            ),
+        }
+    }
+
+    println!("{}", style("Review session over").magenta());
+    Ok(())
+}
+
+// resumes at the selection tree, then tags and imports like any other request. None if it was aborted
+async fn review_request(
+    parked: &ParkedRequest,
+    args: &cli::TtyArgs,
+    vreq_queue: &VideoRequestQueue,
+    acoustid_client: &mut reqwest::Client,
+) -> Result<Option<RequestReport>, anyhow::Error> {
+    if !parked.work_dir.is_dir() {
+        return Err(anyhow!(
+            "Its temp directory '{}' is gone, discard it",
+            parked.work_dir.display()
+        ));
+    }
+
+    'request: loop {
+        let mut report = RequestReport {
+            recordings: parked.recordings.clone(),
+            ..RequestReport::default()
+        };
+
+        for looked_up in &parked.files {
+            let filepath = parked.work_dir.join(&looked_up.filename);
+            match file::select_and_tag_filepath(
+                &filepath,
+                looked_up,
+                acoustid_client,
+                args,
+                vreq_queue,
+                &mut report.recordings,
+            )
+            .await?
+            {
+                FileOutcome::Wtd(WhatToDo::RestartRequest) => continue 'request,
+                FileOutcome::Wtd(WhatToDo::AbortRequest) => return Ok(None),
+                FileOutcome::Done | FileOutcome::Wtd(_) | FileOutcome::Undecided => {}
+            }
+        }
+
+        match video::import_work_dir(&parked.work_dir, args, &mut report).await? {
+            Some(WhatToDo::RestartRequest) => continue 'request,
+            Some(WhatToDo::AbortRequest) => return Ok(None),
+            _ => {}
+        }
+
+        if video::keep_work_dir(args, &parked.work_dir).await? {
+            println!("Persisted directory '{}'", parked.work_dir.display());
+        } else {
+            std::fs::remove_dir_all(&parked.work_dir)?;
+        }
+
+        break 'request Ok(Some(report));
+    }
+}
+
+// Lists the parked requests, or asks the tty to start a review session in its terminal.
+pub(crate) async fn run(args: cli::ReviewArgs) -> Result<(), anyhow::Error> {
+    let client = TtyClient::connect(&args.connection).await?;
+
+    if args.list || args.json {
+        let response = client.get("/review").timeout(Duration::from_secs(5)).send().await?;
+        let parked: Vec<ParkedInfo> = client
+            .error_for_status(response, "couldn't list the parked video requests")
+            .await?
+            .json()
+            .await?;
+
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&parked)?);
+            return Ok(());
+        }
+
+        if parked.is_empty() {
+            println!("No parked video request");
+            return Ok(());
+        }
+
+        let now = unix_now();
+        for info in &parked {
+            println!(
+                "{} (parked {} ago, profile: {}): {}",
+                style(&info.source_url).cyan(),
+                format_age(now.saturating_sub(info.parked_at)),
+                info.profile.as_deref().unwrap_or("<tty's>"),
+                info.reason
+            );
+            println!("  Kept: {}", info.work_dir.display());
+            for file in &info.files {
+                println!("  Undecided: {file}");
+            }
+        }
+
+        return Ok(());
+    }
+
+    let response = client.post("/review").timeout(Duration::from_secs(5)).send().await?;
+    let started: ReviewStarted = client
+        .error_for_status(response, "refused to start a review session")
+        .await?
+        .json()
+        .await?;
+
+    match started.parked {
+        0 => println!("No parked video request to review"),
+        parked => println!(
+            "{} in the tty's terminal, for {parked} parked video request(s), once the current video request is done",
+            style("Review session starting").green()
+        ),
+    }
+
+    Ok(())
+}
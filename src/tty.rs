@@ -1,23 +1,56 @@
-use crate::{cli, lock, net, signals, video};
+use crate::{auth, cli, config, history, lock, net, queue, signals, user, video};
 use console::style;
+use lock::TtyEndpoint;
+use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
 
-pub(crate) fn init(args: Arc<cli::TtyArgs>) -> (std::net::TcpListener, u16) {
+// returns what was written to the lockfile (even if it's disabled)
+pub(crate) fn init(args: Arc<cli::TtyArgs>, token: &str) -> (net::Listener, lock::LockfileContents) {
     let mut instance_lock = if args.dangerously_skip_lock_checks {
         println!("{}", style("WARNING: Skipping lock check!").bold().red());
         None
     } else {
-        Some(lock::InstanceLock::lock_or_panic())
+        Some(lock::InstanceLock::lock_or_panic(&args.instance))
     };
 
+    let mut lockfile_contents = lock::LockfileContents::new(&args.profile, &args.instance);
     if let Some(instance_lock) = instance_lock.as_mut() {
         instance_lock.with_guard_mut(|guard| {
-            // temporarily write port 0 in lockfile
-            lock::write_pid_port(guard, 0).expect("Failed to write PID/PORT to lockfile!");
+            // what a tty that didn't exit cleanly left behind, its endpoint and token are useless
+            lock::clear_contents(guard).expect("Failed to clear the lockfile!");
         })
     };
 
-    let tcpl = match args.port_override {
+    let (listener, endpoint) = if args.unix_socket {
+        let socket_path =
+            lock::default_socket_path(&args.instance).expect("Failed to find a directory for the unix socket");
+        let listener = bind_unix_socket(&socket_path, instance_lock.is_some());
+        (net::Listener::Unix(listener), TtyEndpoint::UnixSocket(socket_path))
+    } else {
+        let (tcpl, port) = bind_tcp(args.port_override);
+        (net::Listener::Tcp(tcpl), TtyEndpoint::Port(port))
+    };
+
+    lockfile_contents.endpoint = Some(endpoint.clone());
+    lockfile_contents.token = Some(String::from(token));
+    // only written once the listener is bound, so that request instances don't have to wait for it
+    if let Some(instance_lock) = instance_lock.as_mut() {
+        instance_lock.with_guard_mut(|guard| {
+            lock::write_contents(guard, &lockfile_contents)
+                .expect("Failed to write the listener's endpoint to lockfile!");
+        })
+    }
+
+    if let Some(instance_lock) = instance_lock {
+        // make sure the instance_lock lives for the whole program's lifetime
+        Box::leak(Box::new(instance_lock));
+    }
+
+    (listener, lockfile_contents)
+}
+
+fn bind_tcp(port_override: Option<u16>) -> (std::net::TcpListener, u16) {
+    let tcpl = match port_override {
         None => std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind TCP listener"),
         Some(port_override) => match std::net::TcpListener::bind(format!("127.0.0.1:{port_override}")) {
             Ok(tcpl) => tcpl,
@@ -35,41 +68,152 @@ pub(crate) fn init(args: Arc<cli::TtyArgs>) -> (std::net::TcpListener, u16) {
         .expect("Failed to retrieve local_addr from TcpListener")
         .port();
 
-    if let Some(instance_lock) = instance_lock.as_mut() {
-        instance_lock.with_guard_mut(|guard| {
-            lock::write_pid_port(guard, port).expect("Failed to write TCP listener port to portfile!");
-        })
-    }
+    (tcpl, port)
+}
 
-    if let Some(instance_lock) = instance_lock {
-        // make sure the instance_lock lives for the whole program's lifetime
-        Box::leak(Box::new(instance_lock));
+fn bind_unix_socket(socket_path: &std::path::Path, locked: bool) -> std::os::unix::net::UnixListener {
+    // a socket left behind by a tty that didn't exit cleanly, nobody else can be using it while we hold the lock
+    if locked && socket_path.exists() {
+        std::fs::remove_file(socket_path).expect("Failed to remove stale unix socket");
     }
 
-    (tcpl, port)
+    let listener = match std::os::unix::net::UnixListener::bind(socket_path) {
+        Ok(listener) => listener,
+        Err(err) => panic!("Failed to bind unix socket {}: {err}", socket_path.display()),
+    };
+
+    // only the user running the tty can talk to it (runtime_dir makes its directory private, so nobody else could
+    // connect before this)
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))
+        .expect("Failed to restrict the unix socket's permissions");
+
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to set unix listener to non-blocking mode");
+
+    listener
 }
 
-pub(crate) async fn run(args: Arc<cli::TtyArgs>) {
+pub(crate) async fn run(profiles: Arc<config::Profiles>) {
+    let args = profiles.selected().clone();
     let init_args = args.clone();
-    let (tcpl, port) = tokio::task::spawn_blocking(move || init(init_args))
+    let token = match &args.token {
+        Some(token) => token.clone(),
+        None => auth::generate_token().expect("Failed to generate the tty token"),
+    };
+    let init_token = token.clone();
+    let (listener, mut tty_info) = tokio::task::spawn_blocking(move || init(init_args, &init_token))
         .await
         .expect("Failed TTY initialization");
+    let endpoint = tty_info.endpoint.clone().expect("init sets the endpoint");
+    // the status route shares the rest
+    tty_info.token = None;
 
-    // using a mpsc queue lets us asynchronously add to the queue,
+    if args.dangerously_skip_lock_checks && args.token.is_none() {
+        // request instances can't read it from the lockfile
+        println!(
+            "{} pass '--token {token}' to request instances",
+            style("The lockfile is disabled,").bold().red()
+        );
+    }
+
+    if args.headless {
+        user::set_headless(user::HeadlessPolicy {
+            retries: args.headless_retries,
+            undecided: args.headless_undecided,
+        });
+        println!(
+            "{} (retries: {}, undecided requests: {})",
+            style("Running headless, prompts are answered by the policy").magenta(),
+            args.headless_retries,
+            args.headless_undecided
+        );
+    } else if args.defer_review {
+        // the policy is suspended during review sessions
+        user::set_headless(user::HeadlessPolicy {
+            retries: args.headless_retries,
+            undecided: cli::UndecidedAction::Park,
+        });
+        println!(
+            "{} (retries: {}), run 'yt-dlp-wrapper review' to pick the recordings",
+            style("Deferring reviews, video requests are handled unattended").magenta(),
+            args.headless_retries
+        );
+    }
+
+    // using a shared queue lets us asynchronously add to the queue,
     // but handle each request one at a time in the terminal
-    let (vreq_send, vreq_receive) = tokio::sync::mpsc::channel(args.max_requests.clamp(1, 256) as usize);
+    let history_file = match history::history_file(&args.instance) {
+        Ok(history_file) => Some(history_file),
+        Err(err) => {
+            eprintln!(
+                "{} finished video requests won't be recorded: {err}",
+                style("No history file,").for_stderr().red()
+            );
+            None
+        }
+    };
+
+    let vreq_queue = Arc::new(
+        queue::VideoRequestQueue::new(
+            args.max_requests.clamp(1, 256) as usize,
+            args.queue_file.clone(),
+            history_file.clone(),
+        )
+        .expect("Failed to restore video requests from the queue file"),
+    );
+
+    if args.retry_failed
+        && let Some(history_file) = &history_file
+    {
+        history::enqueue_retries(&vreq_queue, history_file);
+    }
 
-    let axum_join = net::start_axum_app(vreq_send, tcpl);
+    if let Err(err) = queue::spawn_queue_file_watcher(vreq_queue.clone()) {
+        eprintln!(
+            "{} external edits to the queue file will be ignored: {err}",
+            style("Failed to watch queue file,").for_stderr().red()
+        );
+    }
 
-    println!(
-        "{} Listening on 127.0.0.1:{}",
-        style("TTY instance is running!").green(),
-        port
+    let axum_join = net::start_axum_app(
+        vreq_queue.clone(),
+        profiles.clone(),
+        Arc::new(tty_info),
+        listener,
+        auth::AuthState::new(token, &endpoint, &args.cors_origins),
+        &args.cors_origins,
     );
 
+    match args.instance.as_str() {
+        lock::DEFAULT_INSTANCE => println!(
+            "{} Listening on {}",
+            style("TTY instance is running!").green(),
+            endpoint
+        ),
+        instance => println!(
+            "{} Listening on {}",
+            style(format!("TTY instance '{instance}' is running!")).green(),
+            endpoint
+        ),
+    }
+
     signals::spawn_ctrlc_listener().await;
 
-    video::spawn_video_request_handler(vreq_receive, args).await;
+    user::save_terminal_settings();
+    let mut handler_join = video::spawn_video_request_handler(vreq_queue.clone(), profiles);
 
-    axum_join.await;
+    // returning drops the listener
+    tokio::select! {
+        _ = axum_join => {}
+        // only returns after a graceful stop
+        _ = &mut handler_join => println!("{}", style("TTY instance stopped").green()),
+        () = vreq_queue.stopped_now() => {
+            // dropping the current request kills its commands and removes its temp directory
+            handler_join.abort();
+            let _ = handler_join.await;
+            user::restore_terminal();
+            println!("{}", style("TTY instance stopped").green());
+        }
+    }
 }
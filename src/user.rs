@@ -1,5 +1,9 @@
+use crate::cli;
+use crate::events::{self, Event};
 use console::style;
 use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub(crate) enum WhatToDo {
@@ -94,6 +98,91 @@ macro_rules! double_loop_what_to_do_opt {
     };
 }
 
+// How a headless tty ('--headless') answers the prompts, set once on startup.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HeadlessPolicy {
+    // per video request
+    pub(crate) retries: u32,
+    pub(crate) undecided: cli::UndecidedAction,
+}
+
+static HEADLESS: once_cell::sync::OnceCell<HeadlessPolicy> = once_cell::sync::OnceCell::new();
+static HEADLESS_RETRIES_LEFT: AtomicU32 = AtomicU32::new(0);
+// set during review sessions, when someone is there to answer
+static ATTENDED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_headless(policy: HeadlessPolicy) {
+    HEADLESS.set(policy).expect("The headless policy is only set once");
+}
+
+pub(crate) fn headless() -> Option<HeadlessPolicy> {
+    if ATTENDED.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    HEADLESS.get().copied()
+}
+
+// prompts are shown again, even on an unattended tty, until it's dropped
+pub(crate) struct Attended;
+
+impl Drop for Attended {
+    fn drop(&mut self) {
+        ATTENDED.store(false, Ordering::SeqCst);
+    }
+}
+
+pub(crate) fn attend() -> Attended {
+    ATTENDED.store(true, Ordering::SeqCst);
+    Attended
+}
+
+// called when a video request starts, retries aren't shared between requests
+pub(crate) fn reset_headless_retries() {
+    if let Some(policy) = headless() {
+        HEADLESS_RETRIES_LEFT.store(policy.retries, Ordering::SeqCst);
+    }
+}
+
+// the retries left after this one, None if there's none left
+fn take_headless_retry() -> Option<u32> {
+    HEADLESS_RETRIES_LEFT
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+        .ok()
+        .map(|left| left - 1)
+}
+
+// nobody reads the prompts, so the answers are logged instead
+fn log_headless_answer(prompt: &str, answer: String) {
+    println!("{} {prompt} -> {answer}", style("Headless:").magenta());
+    events::emit(Event::HeadlessAnswer {
+        prompt: prompt.to_string(),
+        answer,
+    });
+}
+
+// None if someone can be asked, otherwise the (logged) policy's answer
+pub(crate) fn headless_answer<T>(prompt: &str, answer: T, answer_display: impl Display) -> Option<T> {
+    headless()?;
+    log_headless_answer(prompt, answer_display.to_string());
+    Some(answer)
+}
+
+// for retry prompts that aren't a WhatToDo, true while the video request has retries left
+pub(crate) fn headless_retry(prompt: &str) -> Option<bool> {
+    headless()?;
+    match take_headless_retry() {
+        Some(left) => {
+            log_headless_answer(prompt, format!("yes ({left} retries left)"));
+            Some(true)
+        }
+        None => {
+            log_headless_answer(prompt, String::from("no (out of retries)"));
+            Some(false)
+        }
+    }
+}
+
 pub(crate) async fn ask_what_to_do(
     message: console::StyledObject<String>,
     allowed: impl AsRef<[WhatToDo]>,
@@ -105,7 +194,37 @@ pub(crate) async fn ask_what_to_do(
         panic!("Internal Error: ask_action_on_command_error received empty 'allowed'")
     }
 
-    let todo = tokio::task::spawn_blocking(move || {
+    let message_text = console::strip_ansi_codes(&message.to_string()).trim().to_string();
+    let prompt = format!("{message_text} What would you like to do?");
+
+    if headless().is_some() {
+        let prompt = match message_text.is_empty() {
+            true => prompt.trim_start(),
+            false => message_text.as_str(),
+        };
+        let retry = allowed.contains(&WhatToDo::Retry).then(take_headless_retry).flatten();
+        return Ok(match retry {
+            Some(left) => {
+                log_headless_answer(
+                    prompt.trim_start(),
+                    format!("{} ({left} retries left)", WhatToDo::Retry),
+                );
+                // failures are often transient (network), don't retry right away
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                WhatToDo::Retry
+            }
+            None => {
+                let todo = match allowed.contains(&WhatToDo::AbortRequest) {
+                    true => WhatToDo::AbortRequest,
+                    false => allowed[0],
+                };
+                log_headless_answer(prompt, todo.to_string());
+                todo
+            }
+        });
+    }
+
+    let todo = interact(prompt.trim_start(), move || {
         dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt(format!("{message}\n{}", style("What would you like to do?").cyan()))
             .default(0)
@@ -117,3 +236,49 @@ pub(crate) async fn ask_what_to_do(
 
     Ok(todo)
 }
+
+// Runs a blocking dialoguer prompt on the blocking thread pool, like every prompt should,
+// letting event stream subscribers know that the tty is waiting for the user.
+pub(crate) async fn interact<T: Send + 'static>(
+    prompt: &str,
+    interact: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, tokio::task::JoinError> {
+    events::emit(Event::PromptPending {
+        prompt: prompt.to_string(),
+    });
+    let result = tokio::task::spawn_blocking(interact).await;
+    events::emit(Event::PromptAnswered);
+
+    result
+}
+
+// what 'stty -g' printed on startup, so that the terminal can be put back in order (see restore_terminal)
+static TERMINAL_SETTINGS: once_cell::sync::OnceCell<String> = once_cell::sync::OnceCell::new();
+
+pub(crate) fn save_terminal_settings() {
+    if !console::Term::stdout().is_term() {
+        return;
+    }
+
+    let output = std::process::Command::new("stty")
+        .arg("-g")
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::null())
+        .output();
+    if let Ok(output) = output
+        && output.status.success()
+    {
+        let _ = TERMINAL_SETTINGS.set(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+}
+
+// a prompt interrupted by 'stop --now' leaves the terminal in raw mode, with the cursor hidden
+pub(crate) fn restore_terminal() {
+    let _ = console::Term::stdout().show_cursor();
+    if let Some(settings) = TERMINAL_SETTINGS.get() {
+        let _ = std::process::Command::new("stty")
+            .arg(settings)
+            .stdin(std::process::Stdio::inherit())
+            .status();
+    }
+}
@@ -1 +1,2 @@
 pub(crate) mod iters;
+pub(crate) mod time;
@@ -0,0 +1,170 @@
+use std::time::SystemTime;
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// short, human readable duration, like '3m' or '2h 5m'
+pub(crate) fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+// see http://howardhinnant.github.io/date_algorithms.html (days_from_civil and civil_from_days)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// like '2026-10-17 21:05 UTC'
+pub(crate) fn format_utc(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+    let seconds = timestamp % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+// midnight (UTC) of a 'YYYY-MM-DD' date
+pub(crate) fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    u64::try_from(days_from_civil(year, month, day) * 86400).ok()
+}
+
+// the inverse of format_age, for a single unit: '90s', '15m', '12h', '7d'
+pub(crate) fn parse_age(age: &str) -> Option<u64> {
+    let unit = match age.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return None,
+    };
+
+    age[..age.len() - 1].parse::<u64>().ok()?.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (date, days since the epoch)
+    const DATES: &[((i64, u32, u32), i64)] = &[
+        ((1970, 1, 1), 0),
+        ((1969, 12, 31), -1),
+        ((2000, 2, 29), 11016),
+        ((2000, 3, 1), 11017),
+        ((2024, 2, 29), 19782),
+        ((2026, 10, 17), 20743),
+        ((2100, 3, 1), 47541),
+    ];
+
+    #[test]
+    fn converts_dates_and_days() {
+        for &(date, days) in DATES {
+            assert_eq!(days_from_civil(date.0, date.1, date.2), days, "wrong days for {date:?}");
+            assert_eq!(civil_from_days(days), date, "wrong date for {days}");
+        }
+    }
+
+    #[test]
+    fn round_trips_every_day() {
+        // a few 400 years cycles around the epoch
+        for days in -146097..3 * 146097 {
+            let (year, month, day) = civil_from_days(days);
+            assert!(day >= 1 && day <= days_in_month(year, month), "invalid date for {days}");
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn formats_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(951_868_799), "2000-02-29 23:59 UTC");
+        assert_eq!(format_utc(1_792_278_300), "2026-10-17 23:05 UTC");
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-02-29"), Some(19782 * 86400));
+        assert_eq!(parse_date("2000-02-29"), Some(11016 * 86400));
+        assert_eq!(parse_date("2026-10-17"), Some(20743 * 86400));
+
+        for invalid in [
+            "2026-02-29",
+            "2100-02-29",
+            "2026-02-31",
+            "2026-04-31",
+            "2026-13-01",
+            "2026-00-10",
+            "2026-10-00",
+            "1969-12-31",
+            "2026-10",
+            "2026-10-17T00:00",
+            "",
+        ] {
+            assert_eq!(parse_date(invalid), None, "accepted {invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("90s"), Some(90));
+        assert_eq!(parse_age("15m"), Some(900));
+        assert_eq!(parse_age("12h"), Some(43200));
+        assert_eq!(parse_age("7d"), Some(604800));
+        assert_eq!(parse_age("0d"), Some(0));
+
+        for invalid in ["", "d", "7", "7w", "-7d", "1.5h", "99999999999999999999d"] {
+            assert_eq!(parse_age(invalid), None, "accepted {invalid}");
+        }
+    }
+}
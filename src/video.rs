@@ -1,70 +1,449 @@
-use crate::user::WhatToDo;
-use crate::{cli, double_loop_what_to_do, double_loop_what_to_do_opt, fingerprinting, handle_ctrlc, process};
+use crate::config::Profiles;
+use crate::prepare::{Prepared, Preparer};
+use crate::queue::{Next, Stage, VideoRequestQueue};
+use crate::user::{self, WhatToDo};
+use crate::utils::time::{format_age, unix_now};
+use crate::youtube;
+use crate::{
+    cli, double_loop_what_to_do, double_loop_what_to_do_opt, fingerprinting, handle_ctrlc, handle_what_to_do,
+    musicbrainz, process, review,
+};
 use console::style;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VideoRequestKind {
+    #[default]
+    Video,
+    // gets expanded into one video request per entry by the tty
+    Playlist,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Site {
+    #[default]
+    YouTube,
+    Bandcamp,
+    SoundCloud,
+    // anything else yt-dlp can handle
+    Generic,
+}
+
+impl Site {
+    fn from_host(host: &str) -> Self {
+        if host_matches(host, "youtube.com")
+            || host_matches(host, "youtube-nocookie.com")
+            || host_matches(host, "youtu.be")
+        {
+            Site::YouTube
+        } else if host_matches(host, "bandcamp.com") {
+            Site::Bandcamp
+        } else if host_matches(host, "soundcloud.com") {
+            Site::SoundCloud
+        } else {
+            Site::Generic
+        }
+    }
+}
+
+impl Display for Site {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Site::YouTube => write!(f, "YouTube"),
+            Site::Bandcamp => write!(f, "Bandcamp"),
+            Site::SoundCloud => write!(f, "SoundCloud"),
+            Site::Generic => write!(f, "generic"),
+        }
+    }
+}
+
+// true if host is domain or one of its subdomains
+pub(crate) fn host_matches(host: &str, domain: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 pub(crate) struct VideoRequest {
-    pub(crate) youtube_id: String,
+    #[serde(default)]
+    pub(crate) site: Site,
+    // the video (or playlist) id for YouTube, the whole url for other sites
+    #[serde(alias = "youtube_id")]
+    pub(crate) target: String,
+    #[serde(default)]
+    pub(crate) kind: VideoRequestKind,
     pub(crate) from_pid: u32,
+    // tty configuration profile, the tty's own one if none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) profile: Option<String>,
+    // MusicBrainz recording ids by filename, confirmed by an earlier attempt at the same request (see history retry)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) chosen_recordings: BTreeMap<String, String>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum VideoRequestUrlParseError {
-    #[error("Unknown url kind")]
+    #[error("Couldn't parse '{0}' as a url: {1}")]
+    InvalidUrl(String, url::ParseError),
+    #[error("Unsupported url scheme '{}', only http(s) urls are supported", .0.scheme())]
+    UnsupportedScheme(Url),
+    #[error("Url '{0}' has no host")]
+    MissingHost(Url),
+    #[error("Url '{0}' is not a YouTube url")]
+    NotYouTube(Url),
+    #[error("Unknown YouTube url kind '{0}'")]
     UnknownUrlKind(Url),
+    #[error("YouTube url '{0}' doesn't contain a video id")]
+    MissingVideoId(Url),
+    #[error("Invalid YouTube video id '{0}', expected 11 characters among A-Z, a-z, 0-9, '-' and '_'")]
+    InvalidVideoId(String),
+    #[error("Invalid YouTube playlist id '{0}'")]
+    InvalidPlaylistId(String),
 }
 
 impl VideoRequest {
-    pub(crate) fn from_yt_url(youtube_url: &str, from_pid: u32) -> Result<Self, anyhow::Error> {
-        let youtube_url: Url = youtube_url.parse()?;
-        let host_str = youtube_url.host_str().unwrap_or_default();
-
-        let segments = youtube_url
-            .path_segments()
-            .map(|segments| segments.collect::<Vec<_>>())
-            .unwrap_or_default();
-
-        let id: String = if host_str.ends_with("youtube.com") || host_str.ends_with("youtube-nocookie.com") {
-            static SEGMENTS_2: [&str; 5] = ["watch", "v", "embed", "e", "shorts"];
-
-            if segments.len() == 1 && segments[0] == "watch" {
-                // handle ...youtube.com/watch?v=XXXXXXXXXXX&foo=bar
-                let mut pairs = youtube_url.query_pairs();
-                let (_, v) = pairs.find(|(k, _)| k == "v").unwrap();
-                v.to_string()
-            } else if segments.len() == 2 && SEGMENTS_2.contains(&segments[0]) {
-                // handle ...youtube.com/(watch|v)/XXXXXXXXXXX?foo=bar
-                segments[1].to_string()
-            } else {
-                return Err(VideoRequestUrlParseError::UnknownUrlKind(youtube_url).into());
+    // YouTube urls (or redirects to them) get their id extracted, other urls are passed as-is to yt-dlp
+    pub(crate) fn from_url(
+        url: &str,
+        from_pid: u32,
+        profile: Option<String>,
+        expand_playlist: bool,
+    ) -> Result<Self, VideoRequestUrlParseError> {
+        if youtube::is_valid_video_id(url.trim()) {
+            return Self::from_yt_url(url, from_pid, profile, expand_playlist);
+        }
+
+        let parsed = youtube::parse_input_url(url)?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(VideoRequestUrlParseError::UnsupportedScheme(parsed));
+        }
+
+        let Some(host) = parsed.host_str() else {
+            return Err(VideoRequestUrlParseError::MissingHost(parsed));
+        };
+
+        match Site::from_host(host) {
+            Site::YouTube => Self::from_yt_url(url, from_pid, profile, expand_playlist),
+            _ if youtube::find_redirect_target(&parsed).is_some() => {
+                Self::from_yt_url(url, from_pid, profile, expand_playlist)
             }
-        } else if host_str.ends_with("youtu.be") {
-            if segments.len() == 1 {
-                // handle ...youtu.be/XXXXXXXXXXX?foo=bar
-                segments[0].to_string()
-            } else {
-                return Err(VideoRequestUrlParseError::UnknownUrlKind(youtube_url).into());
+            site => Ok(Self {
+                site,
+                target: parsed.to_string(),
+                kind: VideoRequestKind::Video,
+                from_pid,
+                profile,
+                chosen_recordings: BTreeMap::new(),
+            }),
+        }
+    }
+
+    // what the request was made from, as far as the tty knows (YouTube requests only carry the id)
+    pub(crate) fn source_url(&self) -> String {
+        match (self.site, self.kind) {
+            (Site::YouTube, VideoRequestKind::Video) => format!("https://www.youtube.com/watch?v={}", self.target),
+            (Site::YouTube, VideoRequestKind::Playlist) => {
+                format!("https://www.youtube.com/playlist?list={}", self.target)
             }
-        } else {
-            // I got lazy: https://gist.github.com/rodrigoborgesdeoliveira/987683cfbfcc8d800192da1e73adc486
-            return Err(VideoRequestUrlParseError::UnknownUrlKind(youtube_url).into());
-        };
+            _ => self.target.clone(),
+        }
+    }
+
+    // whether both requests would download the same thing, regardless of who asked for it and how
+    pub(crate) fn same_target(&self, other: &VideoRequest) -> bool {
+        self.site == other.site && self.target == other.target && self.kind == other.kind
+    }
+
+    // checks what a request instance (or the queue file) sent us, the tty shouldn't trust it blindly
+    pub(crate) fn validate(&self, allowed_hosts: &[String]) -> Result<(), String> {
+        match self.site {
+            Site::YouTube => {
+                let valid = match self.kind {
+                    VideoRequestKind::Video => youtube::is_valid_video_id(&self.target),
+                    VideoRequestKind::Playlist => youtube::is_valid_playlist_id(&self.target),
+                };
+                if !valid {
+                    return Err(format!("Invalid YouTube id '{}'", self.target));
+                }
+            }
+            site => {
+                if self.kind == VideoRequestKind::Playlist {
+                    return Err(format!("Playlist requests are only supported for YouTube, not {site}"));
+                }
+
+                let url: Url = self
+                    .target
+                    .parse()
+                    .map_err(|err| format!("Invalid url '{}': {err}", self.target))?;
+                let host = url.host_str().unwrap_or_default();
+
+                if !matches!(url.scheme(), "http" | "https") || Site::from_host(host) != site {
+                    return Err(format!("Invalid {site} url '{}'", self.target));
+                }
+
+                if !allowed_hosts.iter().any(|allowed| host_matches(host, allowed)) {
+                    return Err(format!("Host '{host}' is not allowed"));
+                }
+            }
+        }
+
+        // the filenames end up joined to the work dir, the ids in MusicBrainz urls
+        for (filename, recording_id) in &self.chosen_recordings {
+            if filename.is_empty() || filename.contains('/') || filename == "." || filename == ".." {
+                return Err(format!("Invalid filename '{filename}'"));
+            }
+            if !musicbrainz::is_valid_mbid(recording_id) {
+                return Err(format!("Invalid MusicBrainz recording id '{recording_id}'"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // also accepts bare video ids and urls that redirect to YouTube, see youtube::parse
+    pub(crate) fn from_yt_url(
+        youtube_url: &str,
+        from_pid: u32,
+        profile: Option<String>,
+        expand_playlist: bool,
+    ) -> Result<Self, VideoRequestUrlParseError> {
+        let (id, kind) = youtube::parse(youtube_url, expand_playlist)?;
 
         Ok(Self {
-            youtube_id: id,
+            site: Site::YouTube,
+            target: id,
+            kind,
             from_pid,
+            profile,
+            chosen_recordings: BTreeMap::new(),
         })
     }
 }
 
-pub(crate) async fn spawn_video_request_handler(
-    mut vreq_receive: tokio::sync::mpsc::Receiver<VideoRequest>,
-    args: Arc<cli::TtyArgs>,
-) {
+// how a video request ended, as reported to request instances waiting for it
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    // ran to completion (the beet import might have been skipped by the profile)
+    Imported,
+    // playlist requests turn into other requests, see RequestReport::enqueued
+    Expanded,
+    Aborted,
+    Failed,
+    Cancelled,
+    // parked by a headless tty, its temp directory is kept (see RequestReport::work_dir)
+    NeedsReview,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Imported => write!(f, "Imported"),
+            Outcome::Expanded => write!(f, "Expanded"),
+            Outcome::Aborted => write!(f, "Aborted"),
+            Outcome::Failed => write!(f, "Failed"),
+            Outcome::Cancelled => write!(f, "Cancelled"),
+            Outcome::NeedsReview => write!(f, "Needs review"),
+        }
+    }
+}
+
+// A headless tty couldn't decide on its own, see '--headless-undecided'.
+// Carries what the review needs to pick up from the selection tree.
+#[derive(Debug, thiserror::Error)]
+#[error("{reason}")]
+pub(crate) struct NeedsReview {
+    pub(crate) reason: String,
+    pub(crate) files: Vec<fingerprinting::file::LookedUpFile>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct ReportedRecording {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    // the AcoustID match the recording was picked from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) acoustid_id: Option<String>,
+    // the file's fingerprint got submitted to AcoustID, bound to this recording
+    #[serde(default)]
+    pub(crate) fingerprint_submitted: bool,
+    // the downloaded file that got tagged, lets a retry of the request reuse the choice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) file: Option<String>,
+}
+
+// what happened while handling a request, filled in along the way
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub(crate) struct RequestReport {
+    // the MusicBrainz recordings the files got tagged with
+    #[serde(default)]
+    pub(crate) recordings: Vec<ReportedRecording>,
+    // where beet put the files, as told by 'beet ls -p'
+    #[serde(default)]
+    pub(crate) imported_paths: Vec<PathBuf>,
+    // ids of the requests a playlist was expanded into
+    #[serde(default)]
+    pub(crate) enqueued: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    // the kept temp directory of requests that need review
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) work_dir: Option<PathBuf>,
+    // what yt-dlp downloaded into the temp directory
+    #[serde(default)]
+    pub(crate) files: Vec<String>,
+    // of the last beet import attempt, none if beet didn't run (or got killed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) beet_exit_code: Option<i32>,
+}
+
+#[derive(serde::Deserialize)]
+struct FlatPlaylist {
+    title: Option<String>,
+    #[serde(default)]
+    entries: Vec<FlatPlaylistEntry>,
+}
+
+#[derive(serde::Deserialize)]
+struct FlatPlaylistEntry {
+    id: String,
+    title: Option<String>,
+}
+
+// asks which playlist entries should be enqueued, and puts them at the front of the queue
+pub(crate) async fn expand_playlist_request(
+    request: VideoRequest,
+    args: &cli::TtyArgs,
+    vreq_queue: &VideoRequestQueue,
+    report: &mut RequestReport,
+) -> Result<RanToCompletion, anyhow::Error> {
+    'request: loop {
+        println!(
+            "Expanding playlist {} from pid {} (profile: {})",
+            &request.target, request.from_pid, &args.profile
+        );
+
+        vreq_queue.set_stage(Stage::ExpandingPlaylist);
+
+        let work_dir = tempfile::tempdir()?;
+        let playlist_url = request.source_url();
+        // the user's yt-dlp args are meant for downloads (and might include '--no-playlist'), so they're not used here
+        let ytdlp_cmd = [
+            args.yt_dlp_display.get().unwrap().as_str(),
+            "--flat-playlist",
+            "-J",
+            "--",
+            &playlist_url,
+        ];
+
+        let output = 'last_command: loop {
+            use std::process::Stdio;
+
+            let yt_dlp_command_execution = process::handle_child_command_execution(
+                &ytdlp_cmd,
+                work_dir.path(),
+                |cmd| {
+                    cmd.stdout(Stdio::piped());
+                },
+                |_| (),
+                process::wait_for_child_output,
+            )
+            .await?
+            .into_success_or_ask_wtd(|status, _output| {
+                let message = format!("yt-dlp returned a non-zero exit code: {}", status);
+
+                (style(message).red(), WhatToDo::all_except(WhatToDo::Continue))
+            })
+            .await?;
+
+            match yt_dlp_command_execution {
+                Ok(output) => break 'last_command output,
+                Err(what_to_do) => {
+                    handle_what_to_do!(what_to_do, [
+                        retry: { continue 'last_command },
+                        restart: { continue 'request },
+                        cont: { unreachable!() },
+                        abort: { break 'request Ok(false) }
+                    ]);
+                }
+            }
+        };
+
+        let playlist: FlatPlaylist = serde_json::from_slice(&output.stdout)?;
+        if playlist.entries.is_empty() {
+            println!("{}", style("The playlist is empty, nothing to enqueue").yellow());
+            break 'request Ok(true);
+        }
+
+        let title = playlist.title.unwrap_or_else(|| request.target.clone());
+        let entries_display: Vec<String> = playlist
+            .entries
+            .iter()
+            .map(|entry| format!("{}: {}", &entry.id, entry.title.as_deref().unwrap_or_default()))
+            .collect();
+        let defaults = vec![true; entries_display.len()];
+
+        let all_entries: Vec<usize> = (0..entries_display.len()).collect();
+        let selections = match user::headless_answer("Select the playlist entries to enqueue", all_entries, "all") {
+            Some(selections) => selections,
+            None => {
+                user::interact("Select the playlist entries to enqueue", move || {
+                    dialoguer::MultiSelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                        .with_prompt(format!(
+                            "Playlist '{}' has {} entries, select the ones to enqueue",
+                            style(title).cyan(),
+                            style(entries_display.len()).cyan().bold()
+                        ))
+                        .items(&entries_display)
+                        .defaults(&defaults)
+                        .max_length(16)
+                        .interact()
+                })
+                .await??
+            }
+        };
+
+        handle_ctrlc!(restart: { continue 'request }, abort: { break 'request Ok(false) });
+
+        let selected: Vec<VideoRequest> = selections
+            .into_iter()
+            .map(|index| VideoRequest {
+                site: Site::YouTube,
+                target: playlist.entries[index].id.clone(),
+                kind: VideoRequestKind::Video,
+                from_pid: request.from_pid,
+                profile: request.profile.clone(),
+                chosen_recordings: BTreeMap::new(),
+            })
+            .collect();
+
+        println!(
+            "{} {} video request(s) from the playlist",
+            style("Enqueued").green(),
+            selected.len()
+        );
+        report.enqueued = vreq_queue.push_front(selected);
+
+        break 'request Ok(true);
+    }
+}
+
+// the handler stops once the queue does (see VideoRequestQueue::stop_after_current)
+pub(crate) fn spawn_video_request_handler(
+    vreq_queue: Arc<VideoRequestQueue>,
+    profiles: Arc<Profiles>,
+) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
+        // clones share the rate limit
         let mut acoustid_client = reqwest::Client::builder()
             .connector_layer(
                 tower::ServiceBuilder::new()
@@ -76,21 +455,112 @@ pub(crate) async fn spawn_video_request_handler(
             .build()
             .expect("Could not initialize acoust_id reqwest client.");
 
-        while let Some(vreq) = vreq_receive.recv().await {
-            let result = process_video_request(vreq, &args, &mut acoustid_client).await;
+        let workers = profiles.selected().workers.clamp(0, 8) as usize;
+        let preparer = (workers > 0)
+            .then(|| Preparer::spawn(workers, vreq_queue.clone(), profiles.clone(), acoustid_client.clone()));
+
+        while let Some(next) = vreq_queue.next().await {
+            let entry = match next {
+                Next::Request(entry) => entry,
+                Next::Review => {
+                    if let Err(err) = review::run_session(&profiles, &vreq_queue, &mut acoustid_client).await {
+                        eprintln!("{}\n{err}", style("Review session failed!").for_stderr().red());
+                    }
+                    continue;
+                }
+            };
+
+            let prepared = match &preparer {
+                Some(preparer) => {
+                    vreq_queue.set_stage(Stage::Downloading);
+                    preparer.take(entry.id).await
+                }
+                None => None,
+            };
+
+            let vreq = entry.request;
+            if let Some(imported_at) = vreq_queue.imported_at(&vreq) {
+                println!(
+                    "{} {} was already imported {} ago",
+                    style("WARNING:").yellow(),
+                    vreq.source_url(),
+                    format_age(unix_now().saturating_sub(imported_at))
+                );
+            }
+
+            // what gets parked, if it comes to that
+            let request = vreq.clone();
+            let kind = vreq.kind;
+            let mut report = RequestReport::default();
+            user::reset_headless_retries();
+            let result = match profiles.get(vreq.profile.as_deref()) {
+                // requests from the queue file didn't go through the http server's checks
+                Some(args) if let Err(err) = vreq.validate(&args.allowed_hosts) => Err(anyhow::anyhow!(err)),
+                Some(args) if vreq.kind == VideoRequestKind::Playlist => {
+                    expand_playlist_request(vreq, args, &vreq_queue, &mut report).await
+                }
+                Some(args) => {
+                    process_video_request(vreq, args, &mut acoustid_client, &vreq_queue, &mut report, prepared).await
+                }
+                // can only happen through the queue file
+                None => Err(anyhow::anyhow!(
+                    "Unknown profile '{}'",
+                    vreq.profile.unwrap_or_default()
+                )),
+            };
 
-            match result {
-                Ok(true) => {}
-                Ok(false) => {}
+            let outcome = match result {
+                Ok(true) if kind == VideoRequestKind::Playlist => Outcome::Expanded,
+                Ok(true) => Outcome::Imported,
+                Ok(false) => Outcome::Aborted,
+                Err(error) if error.is::<NeedsReview>() => {
+                    let needs_review = error.downcast::<NeedsReview>().expect("checked above");
+                    report.error = Some(needs_review.reason.clone());
+                    park(request, needs_review, &report, &profiles.selected().instance)
+                }
                 Err(error) => {
                     eprintln!(
                         "{}\n{error}",
                         style("Failed to handle video request!").for_stderr().red()
-                    )
+                    );
+                    report.error = Some(error.to_string());
+                    Outcome::Failed
                 }
-            }
+            };
+            vreq_queue.finish_current(outcome, report);
+        }
+    })
+}
+
+fn park(request: VideoRequest, needs_review: NeedsReview, report: &RequestReport, instance: &str) -> Outcome {
+    let parked = review::ParkedRequest {
+        request,
+        parked_at: unix_now(),
+        reason: needs_review.reason,
+        work_dir: report.work_dir.clone().unwrap_or_default(),
+        recordings: report.recordings.clone(),
+        files: needs_review.files,
+    };
+
+    match review::park(instance, &parked) {
+        Ok(()) => {
+            println!(
+                "{} {}, kept '{}' ('yt-dlp-wrapper review' picks it up from there)",
+                style("Parked the video request for review:").yellow(),
+                parked.reason,
+                parked.work_dir.display()
+            );
+            Outcome::NeedsReview
         }
-    });
+        Err(err) => {
+            eprintln!(
+                "{} '{}' is kept, but can't be reviewed\n{err}",
+                style("Failed to park the video request!").for_stderr().red(),
+                parked.work_dir.display()
+            );
+            Outcome::Failed
+        }
+    }
 }
 
 pub(crate) type RanToCompletion = bool;
@@ -98,25 +568,33 @@ pub(crate) async fn process_video_request(
     request: VideoRequest,
     args: &cli::TtyArgs,
     acoustid_client: &mut reqwest::Client,
+    vreq_queue: &VideoRequestQueue,
+    report: &mut RequestReport,
+    // downloaded by a background worker, only used the first time around (restarts download again)
+    mut prepared: Option<Prepared>,
 ) -> Result<RanToCompletion, anyhow::Error> {
     'request: loop {
+        *report = RequestReport::default();
         println!(
-            "Processing request for {} from pid {}",
-            &request.youtube_id, request.from_pid
+            "Processing {} request for {} from pid {} (profile: {})",
+            request.site, &request.target, request.from_pid, &args.profile
         );
 
-        let work_dir = tempfile::tempdir()?;
+        let (work_dir, mut prepared_files, downloaded) = match prepared.take() {
+            Some(prepared) => (prepared.work_dir, prepared.files, true),
+            None => (tempfile::tempdir()?, HashMap::new(), false),
+        };
         let work_dir_path = work_dir.path();
 
-        let mut ytdlp_cmd: Vec<&str> = Vec::with_capacity(args.yt_dlp_args.components.len() + 1);
-        ytdlp_cmd.push(args.yt_dlp_display.get().unwrap());
-        for component in &args.yt_dlp_args.components {
-            ytdlp_cmd.push(component); // coerces &String into &str
-        }
-        ytdlp_cmd.push("--");
-        ytdlp_cmd.push(&request.youtube_id);
+        let ytdlp_cmd = yt_dlp_command(args, &request);
 
+        vreq_queue.set_stage(Stage::Downloading);
         'last_command: loop {
+            if downloaded {
+                println!("{}", style("Downloaded in the background").magenta());
+                break 'last_command;
+            }
+
             let yt_dlp_command_execution = process::handle_child_command_execution(
                 &ytdlp_cmd,
                 work_dir_path,
@@ -142,53 +620,167 @@ pub(crate) async fn process_video_request(
             }
         }
 
+        report.files = fingerprinting::file::get_fingerprintable_filenames_in_directory(work_dir_path);
+        report.files.sort();
+
+        vreq_queue.set_stage(Stage::Fingerprinting);
         'fingerprinting: loop {
+            if args.skip_fingerprinting {
+                println!("{}", style("Skipping fingerprinting (profile setting)").magenta());
+                break 'fingerprinting;
+            }
+
             handle_ctrlc!(restart: { continue 'request }, abort: { break 'request Ok(false) });
-            let what_to_do =
-                fingerprinting::file::handle_fingerprinting_process_for_directory(work_dir_path, acoustid_client, args)
-                    .await?;
+            report.recordings.clear();
+            let what_to_do = match fingerprinting::file::handle_fingerprinting_process_for_directory(
+                work_dir_path,
+                acoustid_client,
+                args,
+                vreq_queue,
+                &mut report.recordings,
+                &mut prepared_files,
+                &request.chosen_recordings,
+            )
+            .await
+            {
+                // whoever reviews it picks up from the downloaded files
+                Err(err) if err.is::<NeedsReview>() => {
+                    report.work_dir = Some(work_dir.keep());
+                    return Err(err);
+                }
+                result => result?,
+            };
 
             double_loop_what_to_do_opt!(what_to_do, 'request, 'fingerprinting, Ok(false), none: { break 'fingerprinting });
         }
 
-        let mut beet_cmd: Vec<&str> = Vec::with_capacity(args.beet_args.components.len() + 1);
+        vreq_queue.set_stage(Stage::Importing);
+        match import_work_dir(work_dir_path, args, report).await? {
+            Some(WhatToDo::RestartRequest) => continue 'request,
+            Some(WhatToDo::AbortRequest) => break 'request Ok(false),
+            _ => {}
+        }
+
+        let do_keep_tempdir = keep_work_dir(args, work_dir_path).await?;
+
+        handle_ctrlc!(restart: { continue 'request }, abort: { break 'request Ok(false) });
+
+        if do_keep_tempdir {
+            let work_dir = work_dir.keep();
+            println!("Persisted directory '{}'", work_dir.display());
+        }
+
+        break 'request Ok(true);
+    }
+}
+
+pub(crate) fn yt_dlp_command<'a>(args: &'a cli::TtyArgs, request: &'a VideoRequest) -> Vec<&'a str> {
+    let mut ytdlp_cmd: Vec<&str> = Vec::with_capacity(args.yt_dlp_args.components.len() + 1);
+    ytdlp_cmd.push(args.yt_dlp_display.get().unwrap());
+    for component in &args.yt_dlp_args.components {
+        ytdlp_cmd.push(component); // coerces &String into &str
+    }
+    ytdlp_cmd.push("--");
+    ytdlp_cmd.push(&request.target);
+    ytdlp_cmd
+}
+
+// runs beet in the work dir, only returns a WhatToDo to restart or abort the request
+pub(crate) async fn import_work_dir(
+    work_dir_path: &Path,
+    args: &cli::TtyArgs,
+    report: &mut RequestReport,
+) -> Result<Option<WhatToDo>, anyhow::Error> {
+    report.beet_exit_code = None;
+    'last_command: loop {
+        if args.skip_beet {
+            println!("{}", style("Skipping beet import (profile setting)").magenta());
+            return Ok(None);
+        }
+
+        // beet's own prompts can't be answered by the policy (and stdin is usually /dev/null under a service manager),
+        // so it skips whatever it isn't sure about instead
+        let quiet = user::headless().is_some();
+
+        let mut beet_cmd: Vec<&str> = Vec::with_capacity(args.beet_args.components.len() + 2);
         beet_cmd.push(args.beet_display.get().unwrap());
         for component in &args.beet_args.components {
             beet_cmd.push(component); // coerces &String into &str
         }
+        if quiet
+            && !args
+                .beet_args
+                .components
+                .iter()
+                .any(|component| component == "-q" || component == "--quiet")
+        {
+            beet_cmd.push("-q");
+        }
         beet_cmd.push(".");
 
-        'last_command: loop {
-            let beet_command_execution = process::handle_child_command_execution(
-                &beet_cmd,
-                work_dir_path,
-                |_| (),
-                |_| (),
-                process::wait_for_child,
-            )
-            .await?
-            .into_success_or_ask_wtd(|status, _unit| {
-                let message = format!("beet returned a non-zero exit code: {}", status);
-                (style(message).red(), WhatToDo::all())
-            })
-            .await?;
+        // e.g. every file was already in the library, beet has nothing to do with what yt-dlp left next to them
+        if !fingerprinting::file::has_importable_files(work_dir_path) {
+            println!("{}", style("Nothing left to import, skipping beet import").magenta());
+            return Ok(None);
+        }
 
-            match beet_command_execution {
-                Ok(_unit) => {
-                    break 'last_command;
-                }
-                Err(what_to_do) => {
-                    double_loop_what_to_do!(what_to_do, 'request, 'last_command, Ok(false));
+        if quiet {
+            println!(
+                "{}",
+                style("Running headless, beet gets '-q' and no stdin (it skips what it isn't sure about)").magenta()
+            );
+        }
+
+        let mut failed_exit_code = None;
+        let beet_command_execution = process::handle_child_command_execution(
+            &beet_cmd,
+            work_dir_path,
+            |command| {
+                if quiet {
+                    command.stdin(std::process::Stdio::null());
                 }
+            },
+            |_| (),
+            process::wait_for_child,
+        )
+        .await?
+        .into_success_or_ask_wtd(|status, _unit| {
+            failed_exit_code = status.code();
+            let message = format!("beet returned a non-zero exit code: {}", status);
+            (style(message).red(), WhatToDo::all())
+        })
+        .await?;
+        report.beet_exit_code = match beet_command_execution {
+            Ok(_unit) => Some(0),
+            Err(_) => failed_exit_code,
+        };
+
+        match beet_command_execution {
+            Ok(_unit) => {
+                report.imported_paths = find_imported_paths(args, &report.recordings).await;
+                return Ok(None);
+            }
+            Err(what_to_do) => {
+                handle_what_to_do!(what_to_do, [
+                    retry: { continue 'last_command },
+                    restart: { return Ok(Some(WhatToDo::RestartRequest)) },
+                    cont: { return Ok(None) },
+                    abort: { return Ok(Some(WhatToDo::AbortRequest)) }
+                ]);
             }
         }
+    }
+}
 
-        let do_keep_tempdir = match args.keep_tmp {
-            cli::PromptFlag::Always => true,
-            cli::PromptFlag::Never => false,
-            cli::PromptFlag::Ask => {
+pub(crate) async fn keep_work_dir(args: &cli::TtyArgs, work_dir_path: &Path) -> Result<bool, anyhow::Error> {
+    Ok(match args.keep_tmp {
+        cli::PromptFlag::Always => true,
+        cli::PromptFlag::Never => false,
+        cli::PromptFlag::Ask => match user::headless_answer("Keep the temp directory?", false, "no") {
+            Some(keep) => keep,
+            None => {
                 let work_dir_path_display = work_dir_path.display().to_string();
-                tokio::task::spawn_blocking(move || {
+                user::interact("Keep the temp directory?", move || {
                     dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
                         .with_prompt(format!(
                             "Would you like to {} the temp directory '{}'?",
@@ -202,15 +794,84 @@ pub(crate) async fn process_video_request(
                 })
                 .await??
             }
-        };
-
-        handle_ctrlc!(restart: { continue 'request }, abort: { break 'request Ok(false) });
+        },
+    })
+}
 
-        if do_keep_tempdir {
-            let work_dir = work_dir.into_path();
-            println!("Persisted directory '{}'", work_dir.display());
+// asks beet where the files tagged with the given recordings ended up, a failure just means no paths get reported
+async fn find_imported_paths(args: &cli::TtyArgs, recordings: &[ReportedRecording]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for recording in recordings {
+        match library_paths(args, &recording.id).await {
+            Some(recording_paths) => paths.extend(recording_paths),
+            None => println!(
+                "{}",
+                style(format!(
+                    "Couldn't find the imported files for recording {}",
+                    recording.id
+                ))
+                .yellow()
+            ),
         }
+    }
 
-        break 'request Ok(true);
+    paths
+}
+
+// the files of the beets library tagged with the recording ('beet ls -p mb_trackid:<id>'), none if beet failed
+pub(crate) async fn library_paths(args: &cli::TtyArgs, recording_id: &str) -> Option<Vec<PathBuf>> {
+    // global beet options (e.g. '-c <config>') come before the subcommand
+    let global_args = args
+        .beet_args
+        .components
+        .iter()
+        .take_while(|component| component.as_str() != "import");
+
+    let output = tokio::process::Command::new(args.beet_display.get().unwrap())
+        .args(global_args)
+        .args(["ls", "-p", &format!("mb_trackid:{recording_id}")])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_chosen_recordings() {
+        let mut request = VideoRequest::from_yt_url("dQw4w9WgXcQ", 1, None, false).unwrap();
+        request.chosen_recordings.insert(
+            String::from("song.opus"),
+            String::from("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"),
+        );
+        assert!(request.validate(&[]).is_ok());
+
+        // the filenames get joined to the work dir, the ids end up in MusicBrainz urls
+        for (filename, recording_id) in [
+            ("../song.opus", "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"),
+            ("..", "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"),
+            ("", "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae"),
+            ("song.opus", "8f3471b5-7e6a-48da-86a9-c1c07a0f47a"),
+            ("song.opus", "8F3471B5-7E6A-48DA-86A9-C1C07A0F47AE"),
+            ("song.opus", "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae?inc=x"),
+            ("song.opus", "8f3471b5x7e6a-48da-86a9-c1c07a0f47ae"),
+        ] {
+            let mut invalid = request.clone();
+            invalid.chosen_recordings = BTreeMap::from([(String::from(filename), String::from(recording_id))]);
+            assert!(invalid.validate(&[]).is_err(), "accepted {filename} -> {recording_id}");
+        }
     }
 }
@@ -0,0 +1,358 @@
+use crate::video::{VideoRequestKind, VideoRequestUrlParseError};
+use url::Url;
+
+// redirects (e.g. google search results, oembed) can wrap each other, but not forever
+const MAX_REDIRECT_DEPTH: usize = 4;
+
+pub(crate) fn is_youtube_host(host: &str) -> bool {
+    ["youtube.com", "youtube-nocookie.com", "youtu.be"]
+        .iter()
+        .any(|domain| crate::video::host_matches(host, domain))
+}
+
+// 11 characters of base64url
+pub(crate) fn is_valid_video_id(id: &str) -> bool {
+    id.len() == 11 && id.chars().all(is_id_char)
+}
+
+// playlist ids come in many shapes (PL..., OLAK5uy_..., RD..., UU..., WL, LL), only the alphabet is shared
+pub(crate) fn is_valid_playlist_id(id: &str) -> bool {
+    id.len() >= 2 && id.chars().all(is_id_char)
+}
+
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+// Clipboard contents often lack the scheme ("youtube.com/watch?v=..."), so https is assumed when missing.
+pub(crate) fn parse_input_url(input: &str) -> Result<Url, VideoRequestUrlParseError> {
+    let input = input.trim();
+    match Url::parse(input) {
+        Ok(url) => Ok(url),
+        Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{input}"))
+            .map_err(|err| VideoRequestUrlParseError::InvalidUrl(input.to_string(), err)),
+        Err(err) => Err(VideoRequestUrlParseError::InvalidUrl(input.to_string(), err)),
+    }
+}
+
+// Accepts YouTube urls, redirects to YouTube urls, and bare video ids.
+// Returns the video id, or the playlist id for playlist urls (and for watch urls with a playlist, if expand_playlist).
+pub(crate) fn parse(
+    input: &str,
+    expand_playlist: bool,
+) -> Result<(String, VideoRequestKind), VideoRequestUrlParseError> {
+    let input = input.trim();
+    if is_valid_video_id(input) {
+        return Ok((input.to_string(), VideoRequestKind::Video));
+    }
+
+    parse_url(&parse_input_url(input)?, expand_playlist, 0)
+}
+
+// Finds a YouTube url wrapped in another url's query (e.g. https://www.google.com/url?q=https%3A%2F%2Fyoutu.be%2F...).
+pub(crate) fn find_redirect_target(url: &Url) -> Option<Url> {
+    url.query_pairs()
+        .filter(|(key, _)| matches!(key.as_ref(), "url" | "q" | "u"))
+        .filter_map(|(_, value)| parse_input_url(&value).ok())
+        .find(|target| matches!(target.scheme(), "http" | "https") && target.host_str().is_some_and(is_youtube_host))
+}
+
+fn parse_url(
+    url: &Url,
+    expand_playlist: bool,
+    depth: usize,
+) -> Result<(String, VideoRequestKind), VideoRequestUrlParseError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(VideoRequestUrlParseError::UnsupportedScheme(url.clone()));
+    }
+
+    let Some(host) = url.host_str() else {
+        return Err(VideoRequestUrlParseError::MissingHost(url.clone()));
+    };
+
+    if !is_youtube_host(host) {
+        return match find_redirect_target(url) {
+            Some(target) if depth < MAX_REDIRECT_DEPTH => parse_url(&target, expand_playlist, depth + 1),
+            _ => Err(VideoRequestUrlParseError::NotYouTube(url.clone())),
+        };
+    }
+
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let segments = url
+        .path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let list = query("list");
+    if let Some(list) = list.filter(|_| expand_playlist || segments == ["playlist"]) {
+        // handle ...youtube.com/playlist?list=XXXXXXXXXXX and ...youtube.com/watch?v=XXXXXXXXXXX&list=XXXXXXXXXXX
+        return if is_valid_playlist_id(&list) {
+            Ok((list, VideoRequestKind::Playlist))
+        } else {
+            Err(VideoRequestUrlParseError::InvalidPlaylistId(list))
+        };
+    }
+
+    let id = if crate::video::host_matches(host, "youtu.be") {
+        // handle ...youtu.be/XXXXXXXXXXX?foo=bar
+        match segments.as_slice() {
+            [id] => id.to_string(),
+            [] => return Err(VideoRequestUrlParseError::MissingVideoId(url.clone())),
+            _ => return Err(VideoRequestUrlParseError::UnknownUrlKind(url.clone())),
+        }
+    } else {
+        static SEGMENTS_2: [&str; 6] = ["watch", "v", "embed", "e", "shorts", "live"];
+
+        match segments.as_slice() {
+            // handle ...youtube.com/watch?v=XXXXXXXXXXX&foo=bar
+            ["watch"] => query("v").ok_or_else(|| VideoRequestUrlParseError::MissingVideoId(url.clone()))?,
+            // handle ...youtube.com/(watch|v|embed|e|shorts|live)/XXXXXXXXXXX?foo=bar
+            [kind, id] if SEGMENTS_2.contains(kind) => id.to_string(),
+            // handle ...youtube.com/attribution_link?u=/watch%3Fv%3DXXXXXXXXXXX%26feature%3Dshare
+            ["attribution_link"] => {
+                let target = query("u").ok_or_else(|| VideoRequestUrlParseError::MissingVideoId(url.clone()))?;
+                let target = url
+                    .join(&target)
+                    .map_err(|err| VideoRequestUrlParseError::InvalidUrl(target, err))?;
+                return parse_nested(url, &target, expand_playlist, depth);
+            }
+            // handle ...youtube.com/oembed?url=http%3A//www.youtube.com/watch?v%3DXXXXXXXXXXX&format=json
+            ["oembed"] => {
+                let target = query("url").ok_or_else(|| VideoRequestUrlParseError::MissingVideoId(url.clone()))?;
+                let target = parse_input_url(&target)?;
+                return parse_nested(url, &target, expand_playlist, depth);
+            }
+            // handle the legacy ...youtube.com/user/Username#p/a/u/1/XXXXXXXXXXX
+            ["user", ..] if url.fragment().is_some_and(|fragment| fragment.starts_with("p/")) => {
+                let fragment = url.fragment().unwrap_or_default();
+                let fragment = fragment.split('?').next().unwrap_or_default();
+                fragment.rsplit('/').next().unwrap_or_default().to_string()
+            }
+            _ => return Err(VideoRequestUrlParseError::UnknownUrlKind(url.clone())),
+        }
+    };
+
+    if is_valid_video_id(&id) {
+        Ok((id, VideoRequestKind::Video))
+    } else {
+        Err(VideoRequestUrlParseError::InvalidVideoId(id))
+    }
+}
+
+fn parse_nested(
+    outer: &Url,
+    target: &Url,
+    expand_playlist: bool,
+    depth: usize,
+) -> Result<(String, VideoRequestKind), VideoRequestUrlParseError> {
+    if depth >= MAX_REDIRECT_DEPTH {
+        return Err(VideoRequestUrlParseError::UnknownUrlKind(outer.clone()));
+    }
+
+    parse_url(target, expand_playlist, depth + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // most of these come from https://gist.github.com/rodrigoborgesdeoliveira/987683cfbfcc8d800192da1e73adc486
+    const VIDEO_URLS: &[(&str, &str)] = &[
+        ("http://www.youtube.com/watch?v=-wtIMTCHWuI", "-wtIMTCHWuI"),
+        ("http://youtube.com/watch?v=-wtIMTCHWuI", "-wtIMTCHWuI"),
+        ("http://m.youtube.com/watch?v=-wtIMTCHWuI", "-wtIMTCHWuI"),
+        ("https://www.youtube.com/watch?v=lalOy8Mbfdc", "lalOy8Mbfdc"),
+        ("https://youtube.com/watch?v=lalOy8Mbfdc", "lalOy8Mbfdc"),
+        ("https://m.youtube.com/watch?v=lalOy8Mbfdc", "lalOy8Mbfdc"),
+        ("http://www.youtube.com/watch?v=yZv2daTWRZU&feature=em-uploademail", "yZv2daTWRZU"),
+        ("http://m.youtube.com/watch?v=yZv2daTWRZU&feature=em-uploademail", "yZv2daTWRZU"),
+        ("http://www.youtube.com/watch?v=0zM3nApSvMg&feature=feedrec_grec_index", "0zM3nApSvMg"),
+        ("http://www.youtube.com/watch?v=0zM3nApSvMg#t=0m10s", "0zM3nApSvMg"),
+        ("http://www.youtube.com/watch?v=cKZDdG9FTKY&feature=channel", "cKZDdG9FTKY"),
+        (
+            "http://www.youtube.com/watch?v=lalOy8Mbfdc&playnext_from=TL&videos=osPknwzXEas&feature=sub",
+            "lalOy8Mbfdc",
+        ),
+        ("http://www.youtube.com/watch?v=dQw4w9WgXcQ&feature=youtu.be", "dQw4w9WgXcQ"),
+        ("http://www.youtube.com/watch?v=0zM3nApSvMg&feature=youtu.be&t=1", "0zM3nApSvMg"),
+        ("http://www.youtube.com/watch?v=lalOy8Mbfdc&feature=youtube_gdata_player", "lalOy8Mbfdc"),
+        (
+            "http://www.youtube.com/watch?v=ishbTyLs6ps&list=PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ&index=106&shuffle=2655",
+            "ishbTyLs6ps",
+        ),
+        ("http://www.youtube.com/watch?feature=player_embedded&v=dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("http://www.youtube.com/watch?app=desktop&v=dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("https://www.youtube.com/watch/-wtIMTCHWuI", "-wtIMTCHWuI"),
+        ("http://www.youtube.com/v/dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("http://www.youtube.com/v/-wtIMTCHWuI?version=3&autohide=1", "-wtIMTCHWuI"),
+        ("http://www.youtube.com/v/0zM3nApSvMg?fs=1&amp;hl=en_US&amp;rel=0", "0zM3nApSvMg"),
+        ("http://www.youtube.com/embed/lalOy8Mbfdc", "lalOy8Mbfdc"),
+        ("http://www.youtube.com/embed/nas1rJpm7wY?rel=0", "nas1rJpm7wY"),
+        ("https://www.youtube.com/embed/M7lc1UVf-VE?autoplay=1&origin=http://example.com", "M7lc1UVf-VE"),
+        ("http://www.youtube.com/e/dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("https://www.youtube.com/shorts/j9rZxAF3C0I", "j9rZxAF3C0I"),
+        ("https://youtube.com/shorts/0dPkkQeRwTI?feature=share", "0dPkkQeRwTI"),
+        ("https://www.youtube.com/live/8hBmepWUJoc?si=iS9cvDNzm7zhdXE5", "8hBmepWUJoc"),
+        ("https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share", "dQw4w9WgXcQ"),
+        ("http://www.youtube.com/user/IngridMichaelsonVEVO#p/a/u/1/QdK8U-VIH_o", "QdK8U-VIH_o"),
+        ("http://www.youtube.com/user/Scobleizer#p/u/1/1p3vcRhsYGo?rel=0", "1p3vcRhsYGo"),
+        ("http://www.youtube.com/user/SilkRoadTheatre#p/a/u/2/6dwqZw0j_jY", "6dwqZw0j_jY"),
+        (
+            "http://www.youtube.com/attribution_link?a=JdfC0C9V6ZI&u=%2Fwatch%3Fv%3DEhxJLojIE_o%26feature%3Dshare",
+            "EhxJLojIE_o",
+        ),
+        (
+            "https://www.youtube.com/attribution_link?a=8g8kPrPIi-ecwIsS&u=/watch%3Fv%3DyZv2daTWRZU%26feature%3Dem-uploademail",
+            "yZv2daTWRZU",
+        ),
+        (
+            "http://www.youtube.com/oembed?url=http%3A//www.youtube.com/watch?v%3D-wtIMTCHWuI&format=json",
+            "-wtIMTCHWuI",
+        ),
+        ("http://youtu.be/-wtIMTCHWuI", "-wtIMTCHWuI"),
+        ("http://youtu.be/dQw4w9WgXcQ?feature=youtube_gdata_player", "dQw4w9WgXcQ"),
+        ("https://youtu.be/M9bq_alk-sw?si=B_RZg_I-lLaa7UU-", "M9bq_alk-sw"),
+        ("http://www.youtube-nocookie.com/embed/lalOy8Mbfdc?rel=0", "lalOy8Mbfdc"),
+        ("http://www.youtube-nocookie.com/v/6L3ZvIMwZFM?version=3&hl=en_US&rel=0", "6L3ZvIMwZFM"),
+        // no scheme, bare ids and surrounding whitespace, as they might come from the clipboard
+        ("youtube.com/watch?v=dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("www.youtube.com/watch?v=dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("youtu.be/dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("dQw4w9WgXcQ", "dQw4w9WgXcQ"),
+        ("  https://youtu.be/dQw4w9WgXcQ\n", "dQw4w9WgXcQ"),
+        // redirects
+        (
+            "https://www.google.com/url?sa=t&rct=j&q=&esrc=s&source=web&cd=&url=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3DdQw4w9WgXcQ&usg=AOvVaw0",
+            "dQw4w9WgXcQ",
+        ),
+        ("https://www.google.com/url?q=https://youtu.be/dQw4w9WgXcQ&sa=D", "dQw4w9WgXcQ"),
+    ];
+
+    const PLAYLIST_URLS: &[(&str, &str)] = &[
+        (
+            "https://www.youtube.com/playlist?list=PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ",
+            "PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ",
+        ),
+        (
+            "https://music.youtube.com/playlist?list=OLAK5uy_n4xYBs2Hy5cS1s7mYVZ2Y8qpZdcIqIoUA",
+            "OLAK5uy_n4xYBs2Hy5cS1s7mYVZ2Y8qpZdcIqIoUA",
+        ),
+        ("https://m.youtube.com/playlist?list=WL", "WL"),
+    ];
+
+    const EXPANDED_PLAYLIST_URLS: &[(&str, &str)] = &[
+        (
+            "http://www.youtube.com/watch?v=ishbTyLs6ps&list=PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ&index=106&shuffle=2655",
+            "PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ",
+        ),
+        (
+            "https://youtu.be/ishbTyLs6ps?list=PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ",
+            "PLGup6kBfcU7Le5laEaCLgTKtlDcxMqGxZ",
+        ),
+    ];
+
+    #[test]
+    fn parses_video_urls() {
+        for (url, id) in VIDEO_URLS {
+            match parse(url, false) {
+                Ok((parsed, kind)) => {
+                    assert_eq!(parsed, *id, "wrong id for {url}");
+                    assert_eq!(kind, VideoRequestKind::Video, "wrong kind for {url}");
+                }
+                Err(err) => panic!("failed to parse {url}: {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_playlist_urls() {
+        for (url, id) in PLAYLIST_URLS {
+            for expand_playlist in [false, true] {
+                let (parsed, kind) = parse(url, expand_playlist).unwrap_or_else(|err| panic!("{url}: {err}"));
+                assert_eq!(parsed, *id, "wrong id for {url}");
+                assert_eq!(kind, VideoRequestKind::Playlist, "wrong kind for {url}");
+            }
+        }
+
+        for (url, id) in EXPANDED_PLAYLIST_URLS {
+            let (parsed, kind) = parse(url, true).unwrap_or_else(|err| panic!("{url}: {err}"));
+            assert_eq!(parsed, *id, "wrong id for {url}");
+            assert_eq!(kind, VideoRequestKind::Playlist, "wrong kind for {url}");
+        }
+    }
+
+    type ErrorCheck = fn(&VideoRequestUrlParseError) -> bool;
+
+    #[test]
+    fn rejects_invalid_urls() {
+        let cases: &[(&str, ErrorCheck)] = &[
+            ("https://www.youtube.com/watch?feature=share", |err| {
+                matches!(err, VideoRequestUrlParseError::MissingVideoId(_))
+            }),
+            ("https://www.youtube.com/watch?v=tooShort", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidVideoId(_))
+            }),
+            ("https://youtu.be/dQw4w9WgXcQ-way-too-long", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidVideoId(_))
+            }),
+            ("https://youtu.be/", |err| {
+                matches!(err, VideoRequestUrlParseError::MissingVideoId(_))
+            }),
+            ("https://www.youtube.com/watch?v=dQw4w9Wg%3CQ", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidVideoId(_))
+            }),
+            ("https://www.youtube.com/playlist?list=PL%20bad", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidPlaylistId(_))
+            }),
+            ("https://www.youtube.com/channel/UC38IQsAvIsxxjztdMZQtwHA", |err| {
+                matches!(err, VideoRequestUrlParseError::UnknownUrlKind(_))
+            }),
+            ("https://www.youtube.com/", |err| {
+                matches!(err, VideoRequestUrlParseError::UnknownUrlKind(_))
+            }),
+            ("https://vimeo.com/76979871", |err| {
+                matches!(err, VideoRequestUrlParseError::NotYouTube(_))
+            }),
+            ("https://www.google.com/url?q=https://example.com/", |err| {
+                matches!(err, VideoRequestUrlParseError::NotYouTube(_))
+            }),
+            ("ftp://youtube.com/watch?v=dQw4w9WgXcQ", |err| {
+                matches!(err, VideoRequestUrlParseError::UnsupportedScheme(_))
+            }),
+            ("", |err| matches!(err, VideoRequestUrlParseError::InvalidUrl(..))),
+            ("some random clipboard content", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidUrl(..))
+            }),
+            ("http://[::1", |err| {
+                matches!(err, VideoRequestUrlParseError::InvalidUrl(..))
+            }),
+        ];
+
+        for (url, expected) in cases {
+            match parse(url, false) {
+                Ok(parsed) => panic!("{url:?} should not parse, got {parsed:?}"),
+                Err(err) => assert!(expected(&err), "unexpected error for {url:?}: {err:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn stops_following_nested_redirects() {
+        let mut url = String::from("https://youtu.be/dQw4w9WgXcQ");
+        for _ in 0..=MAX_REDIRECT_DEPTH {
+            url = Url::parse_with_params("https://www.google.com/url", &[("q", &url)])
+                .unwrap()
+                .to_string();
+        }
+
+        assert!(matches!(
+            parse(&url, false),
+            Err(VideoRequestUrlParseError::NotYouTube(_))
+        ));
+    }
+}